@@ -1,35 +1,43 @@
-use intcode::Machine;
+use intcode::{IntcodeError, Machine};
 
-fn main() {
+fn main() -> Result<(), IntcodeError> {
     let mut machine = Machine::default();
 
     // Part 1
-    machine.load_program("input.txt");
+    machine.load_program("input.txt")?;
     machine.input(1);
-    machine.run();
+    machine.run()?;
+    println!("Part 1 output: {:?}", machine.drain_output());
 
     // Part 2
-    machine.load_program("input.txt");
+    machine.load_program("input.txt")?;
     machine.input(5);
-    machine.run();
+    machine.run()?;
+    println!("Part 2 output: {:?}", machine.drain_output());
+
+    Ok(())
 }
 
 #[test]
 fn test_day5_puzzle1() {
     let mut machine = Machine::default();
-    machine.load_program("input.txt");
+    machine.load_program("input.txt").unwrap();
     machine.input(1);
     let output = machine.run();
 
-    assert_eq!(output, Some(9_938_601));
+    assert_eq!(output, Ok(Some(9_938_601)));
+
+    // Every diagnostic test before the answer should pass
+    let diagnostics = machine.drain_output();
+    assert!(diagnostics[..diagnostics.len() - 1].iter().all(|&d| d == 0));
 }
 
 #[test]
 fn test_day5_puzzle2() {
     let mut machine = Machine::default();
-    machine.load_program("input.txt");
+    machine.load_program("input.txt").unwrap();
     machine.input(5);
     let output = machine.run();
 
-    assert_eq!(output, Some(4_283_952));
+    assert_eq!(output, Ok(Some(4_283_952)));
 }
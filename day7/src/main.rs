@@ -1,33 +1,34 @@
-use intcode::Machine;
+use intcode::pipeline::{Pipeline, Topology};
+use intcode::{read_program, IntcodeError};
 use itertools::Itertools;
-use std::cmp;
-use std::fs;
-
-fn main() {
-    let input_str = fs::read_to_string("input").expect("Something went wrong reading the file");
-
-    let program: Vec<isize> = input_str
-        .split(',')
-        .map(|s| s.parse::<isize>().expect("Unable to parse integer"))
-        .collect();
-
-    let mut amplifiers: Vec<_> = (0..5).map(|_| Machine::default()).collect();
-
-    let mut max_output = 0;
-    let mut output = 0;
-
-    for seq in (0..5).permutations(5) {
-        println!("{:?}", seq);
-        for (i, a) in amplifiers.iter_mut().enumerate() {
-            a.init(&program);
-            a.input(seq[i]);
-            a.input(output);
-            output = a.run().unwrap();
-        }
-        max_output = cmp::max(output, max_output);
+
+fn max_signal(
+    program: &[isize],
+    phases: &[isize],
+    topology: Topology,
+) -> Result<isize, IntcodeError> {
+    let mut max = None;
+    for seq in phases.iter().copied().permutations(phases.len()) {
+        max = max.max(Pipeline::new(program, &seq, topology).run(0)?);
     }
 
-    println!("Part 1 answer: {}", max_output);
+    // A program whose last stage never outputs anything gives a signal of 0
+    Ok(max.unwrap_or_default())
+}
+
+fn main() -> Result<(), IntcodeError> {
+    let program = read_program("input")?;
+
+    println!(
+        "Part 1 answer: {}",
+        max_signal(&program, &[0, 1, 2, 3, 4], Topology::Linear)?
+    );
+    println!(
+        "Part 2 answer: {}",
+        max_signal(&program, &[5, 6, 7, 8, 9], Topology::Feedback)?
+    );
+
+    Ok(())
 }
 
 #[test]
@@ -36,19 +37,13 @@ fn test_example_1() {
         3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
     ];
 
-    let seq = [4, 3, 2, 1, 0];
-
-    let mut amplifiers: Vec<_> = (0..5).map(|_| Machine::default()).collect();
-
-    let mut output = Some(0);
-    for (i, a) in amplifiers.iter_mut().enumerate() {
-        a.init(&p);
-        a.input(seq[i]);
-        a.input(output.unwrap());
-        output = a.run();
-    }
+    let pipeline = Pipeline::new(&p, &[4, 3, 2, 1, 0], Topology::Linear);
 
-    assert_eq!(output, Some(43210));
+    assert_eq!(pipeline.run(0), Ok(Some(43210)));
+    assert_eq!(
+        max_signal(&p, &[0, 1, 2, 3, 4], Topology::Linear),
+        Ok(43210)
+    );
 }
 
 #[test]
@@ -58,19 +53,9 @@ fn test_example_2() {
         0, 0,
     ];
 
-    let seq = [0, 1, 2, 3, 4];
-
-    let mut amplifiers: Vec<_> = (0..5).map(|_| Machine::default()).collect();
-
-    let mut output = Some(0);
-    for (i, a) in amplifiers.iter_mut().enumerate() {
-        a.init(&p);
-        a.input(seq[i]);
-        a.input(output.unwrap());
-        output = a.run();
-    }
+    let pipeline = Pipeline::new(&p, &[0, 1, 2, 3, 4], Topology::Linear);
 
-    assert_eq!(output, Some(54321));
+    assert_eq!(pipeline.run(0), Ok(Some(54321)));
 }
 
 #[test]
@@ -80,19 +65,49 @@ fn test_example_3() {
         31, 31, 1, 32, 31, 31, 4, 31, 99, 0, 0, 0,
     ];
 
-    let seq = [1, 0, 4, 3, 2];
+    let pipeline = Pipeline::new(&p, &[1, 0, 4, 3, 2], Topology::Linear);
 
-    let mut amplifiers: Vec<_> = (0..5).map(|_| Machine::default()).collect();
+    assert_eq!(pipeline.run(0), Ok(Some(65210)));
+}
 
-    let mut output = Some(0);
-    for (i, a) in amplifiers.iter_mut().enumerate() {
-        a.init(&p);
-        a.input(seq[i]);
-        a.input(output.unwrap());
-        output = a.run();
-    }
+#[test]
+fn test_feedback_example_1() {
+    let p = [
+        3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1, 28,
+        1005, 28, 6, 99, 0, 0, 5,
+    ];
 
-    assert_eq!(output, Some(65210));
+    let pipeline = Pipeline::new(&p, &[9, 8, 7, 6, 5], Topology::Feedback);
+
+    assert_eq!(pipeline.run(0), Ok(Some(139_629_729)));
+    assert_eq!(
+        max_signal(&p, &[5, 6, 7, 8, 9], Topology::Feedback),
+        Ok(139_629_729)
+    );
+}
+
+#[test]
+fn test_feedback_example_2() {
+    let p = [
+        3, 52, 1001, 52, -5, 52, 3, 53, 1, 52, 56, 54, 1007, 54, 5, 55, 1005, 55, 26, 1001, 54, -5,
+        54, 1105, 1, 12, 1, 53, 54, 53, 1008, 54, 0, 55, 1001, 55, 1, 55, 2, 53, 55, 53, 4, 53,
+        1001, 56, -1, 56, 1005, 56, 6, 99, 0, 0, 0, 0, 10,
+    ];
+
+    let pipeline = Pipeline::new(&p, &[9, 7, 8, 5, 6], Topology::Feedback);
+
+    assert_eq!(pipeline.run(0), Ok(Some(18_216)));
+}
+
+#[test]
+fn test_malformed_program() {
+    assert_eq!(
+        max_signal(&[3, 0, 42], &[0, 1], Topology::Linear),
+        Err(IntcodeError::UnknownInstruction {
+            instruction_pointer: 2,
+            code: 42
+        })
+    );
 }
 
 // 8782503524126251271 too high
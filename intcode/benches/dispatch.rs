@@ -0,0 +1,159 @@
+// Measures instructions per second of the machine, with and without the
+// decode cache, against the HashMap based dispatcher it replaced. Run with
+// `cargo bench`.
+
+mod legacy;
+
+use std::time::{Duration, Instant};
+
+use intcode::asm::assemble;
+use intcode::{Machine, Status};
+
+const ROUNDS: usize = 5;
+
+struct Workload {
+    name: &'static str,
+    source: &'static str,
+    input: isize,
+}
+
+const WORKLOADS: [Workload; 3] = [
+    // Position mode arithmetic and a conditional jump
+    Workload {
+        name: "countdown",
+        source: "
+                IN n
+        loop:   ADD n, #-1, n
+                JIT n, #loop
+                OUT n
+                HALT
+        n:      DATA 0
+        ",
+        input: 1_000_000,
+    },
+    // Relative mode addressing throughout
+    Workload {
+        name: "relative sum",
+        source: "
+                ARB #acc
+                IN @1
+        loop:   ADD @0, @1, @0
+                ADD @1, #-1, @1
+                JIT @1, #loop
+                OUT @0
+                HALT
+        acc:    DATA 0, 0
+        ",
+        input: 1_000_000,
+    },
+    // Every three argument operation
+    Workload {
+        name: "compare",
+        source: "
+                IN n
+        loop:   ADD i, #1, i
+                MUL i, #3, t
+                LT t, n, c
+                EQ i, n, done
+                JIF done, #loop
+                OUT t
+                HALT
+        n:      DATA 0
+        i:      DATA 0
+        t:      DATA 0
+        c:      DATA 0
+        done:   DATA 0
+        ",
+        input: 500_000,
+    },
+];
+
+fn count_instructions(program: &[isize], input: isize) -> usize {
+    let mut m = Machine::default();
+    m.init(program);
+    m.input(input);
+
+    let mut count = 1;
+    while m.step().expect("benchmark program failed") != Status::Halted {
+        count += 1;
+    }
+    count
+}
+
+// Fastest of several runs, to keep scheduling noise out of the comparison
+fn time<T>(mut f: impl FnMut() -> T) -> (Duration, T) {
+    let mut best = None;
+    let mut result = None;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        let r = f();
+        let elapsed = start.elapsed();
+        best = Some(best.map_or(elapsed, |b: Duration| b.min(elapsed)));
+        result = Some(r);
+    }
+    (best.unwrap(), result.unwrap())
+}
+
+fn per_second(instructions: usize, elapsed: Duration) -> f64 {
+    instructions as f64 / elapsed.as_secs_f64()
+}
+
+fn main() {
+    println!(
+        "{:<14} {:>12} {:>16} {:>16} {:>8} {:>16} {:>8}",
+        "workload",
+        "instructions",
+        "legacy instr/s",
+        "current instr/s",
+        "speedup",
+        "cached instr/s",
+        "speedup"
+    );
+
+    for workload in WORKLOADS.iter() {
+        let program = assemble(workload.source).expect("benchmark program does not assemble");
+        let instructions = count_instructions(&program, workload.input);
+
+        let (legacy_time, legacy_output) = time(|| {
+            legacy::Machine::new(&program)
+                .run(&[workload.input])
+                .expect("legacy machine failed")
+        });
+
+        let run = |cached: bool| {
+            let mut m = Machine::default();
+            m.set_decode_cache(cached);
+            m.init(&program);
+            m.input(workload.input);
+            m.run().expect("machine failed");
+            m.drain_output()
+        };
+        let (current_time, current_output) = time(|| run(false));
+        let (cached_time, cached_output) = time(|| run(true));
+
+        assert_eq!(
+            legacy_output, current_output,
+            "{}: outputs differ",
+            workload.name
+        );
+        assert_eq!(
+            legacy_output, cached_output,
+            "{}: cached outputs differ",
+            workload.name
+        );
+
+        let legacy = per_second(instructions, legacy_time);
+        let current = per_second(instructions, current_time);
+        let cached = per_second(instructions, cached_time);
+        println!(
+            "{:<14} {:>12} {:>16.0} {:>16.0} {:>7.2}x {:>16.0} {:>7.2}x",
+            workload.name,
+            instructions,
+            legacy,
+            current,
+            current / legacy,
+            cached,
+            cached / legacy
+        );
+    }
+}
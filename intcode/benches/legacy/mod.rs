@@ -0,0 +1,216 @@
+// The decoder and dispatcher as they were before dispatch tables: two
+// `HashMap` lookups per instruction and a freshly allocated argument list.
+// Kept here, trimmed to what the benchmarks need, as the baseline to
+// measure against.
+
+use std::collections::HashMap;
+
+use intcode::IntcodeError;
+
+const PAGE_SIZE: usize = 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Operation {
+    Add,
+    Mul,
+    In,
+    Out,
+    Jit,
+    Jif,
+    Lt,
+    Eq,
+    Arb,
+    Halt,
+}
+
+#[derive(Copy, Clone)]
+enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+struct Instruction {
+    operation: Operation,
+    args: Vec<usize>,
+}
+
+type InstructionCall = fn(&mut Machine, &Instruction) -> Result<Option<isize>, IntcodeError>;
+
+#[derive(Default)]
+pub struct Machine {
+    op_codes: HashMap<isize, (Operation, usize)>,
+    instructions: HashMap<Operation, InstructionCall>,
+    pages: HashMap<usize, Box<[isize; PAGE_SIZE]>>,
+    instruction_pointer: usize,
+    relative_base: isize,
+    jump_flag: bool,
+    halt_flag: bool,
+    input: Vec<isize>,
+}
+
+impl Mode {
+    fn from_code(code: isize, offset: usize) -> Option<Mode> {
+        match (code / 100 / 10_isize.pow(offset as u32)) % 10 {
+            0 => Some(Mode::Position),
+            1 => Some(Mode::Immediate),
+            2 => Some(Mode::Relative),
+            _ => None,
+        }
+    }
+}
+
+impl Machine {
+    pub fn new(program: &[isize]) -> Machine {
+        let mut m = Machine::default();
+        for (loc, val) in program.iter().enumerate() {
+            m.set(loc, *val);
+        }
+
+        m.insert(1, Operation::Add, 3, |m, i| {
+            m.set(i.args[2], m.get(i.args[0]).wrapping_add(m.get(i.args[1])));
+            Ok(None)
+        });
+        m.insert(2, Operation::Mul, 3, |m, i| {
+            m.set(i.args[2], m.get(i.args[0]).wrapping_mul(m.get(i.args[1])));
+            Ok(None)
+        });
+        m.insert(3, Operation::In, 1, |m, i| {
+            let input = m.input.pop().ok_or(IntcodeError::NoInput {
+                instruction_pointer: m.instruction_pointer,
+                code: m.get(m.instruction_pointer),
+            })?;
+            m.set(i.args[0], input);
+            Ok(None)
+        });
+        m.insert(4, Operation::Out, 1, |m, i| Ok(Some(m.get(i.args[0]))));
+        m.insert(5, Operation::Jit, 2, |m, i| {
+            if m.get(i.args[0]) != 0 {
+                m.instruction_pointer = m.address(m.get(i.args[1]))?;
+                m.jump_flag = true;
+            }
+            Ok(None)
+        });
+        m.insert(6, Operation::Jif, 2, |m, i| {
+            if m.get(i.args[0]) == 0 {
+                m.instruction_pointer = m.address(m.get(i.args[1]))?;
+                m.jump_flag = true;
+            }
+            Ok(None)
+        });
+        m.insert(7, Operation::Lt, 3, |m, i| {
+            m.set(i.args[2], (m.get(i.args[0]) < m.get(i.args[1])) as isize);
+            Ok(None)
+        });
+        m.insert(8, Operation::Eq, 3, |m, i| {
+            m.set(i.args[2], (m.get(i.args[0]) == m.get(i.args[1])) as isize);
+            Ok(None)
+        });
+        m.insert(9, Operation::Arb, 1, |m, i| {
+            m.relative_base += m.get(i.args[0]);
+            Ok(None)
+        });
+        m.insert(99, Operation::Halt, 0, |m, _i| {
+            m.halt_flag = true;
+            Ok(None)
+        });
+
+        m
+    }
+
+    fn insert(&mut self, code: isize, op: Operation, arg_len: usize, f: InstructionCall) {
+        self.op_codes.insert(code, (op, arg_len));
+        self.instructions.insert(op, f);
+    }
+
+    fn get(&self, loc: usize) -> isize {
+        match self.pages.get(&(loc / PAGE_SIZE)) {
+            Some(page) => page[loc % PAGE_SIZE],
+            None => 0,
+        }
+    }
+
+    fn set(&mut self, loc: usize, val: isize) {
+        let page = self
+            .pages
+            .entry(loc / PAGE_SIZE)
+            .or_insert_with(|| Box::new([0; PAGE_SIZE]));
+        page[loc % PAGE_SIZE] = val;
+    }
+
+    fn address(&self, val: isize) -> Result<usize, IntcodeError> {
+        if val < 0 {
+            return Err(IntcodeError::NegativeAddress {
+                instruction_pointer: self.instruction_pointer,
+                code: self.get(self.instruction_pointer),
+                address: val,
+            });
+        }
+        Ok(val as usize)
+    }
+
+    fn parse(&self) -> Result<Instruction, IntcodeError> {
+        let ip = self.instruction_pointer;
+        let code = self.get(ip);
+
+        let mode = |i: usize| {
+            Mode::from_code(code, i).ok_or(IntcodeError::UnknownParameterMode {
+                instruction_pointer: ip,
+                code,
+                address: ip + i + 1,
+            })
+        };
+
+        let location = |i: usize| -> Result<usize, IntcodeError> {
+            match mode(i)? {
+                Mode::Position => self.address(self.get(ip + i + 1)),
+                Mode::Immediate => Ok(ip + i + 1),
+                Mode::Relative => self.address(self.relative_base + self.get(ip + i + 1)),
+            }
+        };
+
+        match self.op_codes.get(&(code % 100)) {
+            Some(&(operation, arg_len)) => Ok(Instruction {
+                operation,
+                args: (0..arg_len).map(location).collect::<Result<_, _>>()?,
+            }),
+            None => Err(IntcodeError::UnknownInstruction {
+                instruction_pointer: ip,
+                code,
+            }),
+        }
+    }
+
+    /// Runs the program to completion, returning its outputs
+    pub fn run(&mut self, input: &[isize]) -> Result<Vec<isize>, IntcodeError> {
+        self.input = input.iter().rev().copied().collect();
+        let mut outputs = Vec::new();
+
+        loop {
+            let instruction = self.parse()?;
+            let f = match self.instructions.get(&instruction.operation) {
+                Some(f) => *f,
+                None => {
+                    return Err(IntcodeError::UnknownOperation {
+                        instruction_pointer: self.instruction_pointer,
+                        code: self.get(self.instruction_pointer),
+                    })
+                }
+            };
+
+            if let Some(output) = f(self, &instruction)? {
+                outputs.push(output);
+            }
+
+            if self.halt_flag {
+                return Ok(outputs);
+            }
+
+            if self.jump_flag {
+                self.jump_flag = false;
+            } else {
+                self.instruction_pointer += instruction.args.len() + 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// Many programs talk to the user in ASCII: input is typed one character code
+// at a time, each line ending with a newline, and output is text with the
+// occasional number outside the ASCII range, which is usually the answer.
+
+use std::convert::TryFrom;
+
+use crate::{Cell, IntcodeError, Machine};
+
+/// Text collected from a machine's outputs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AsciiOutput<C = isize> {
+    pub text: String,
+    /// Outputs outside the ASCII range, in the order they were produced
+    pub values: Vec<C>,
+}
+
+/// The character for an output, if it is in the ASCII range
+pub fn to_ascii<C: Cell>(value: &C) -> Option<char> {
+    value
+        .to_isize()
+        .and_then(|v| u8::try_from(v).ok())
+        .filter(u8::is_ascii)
+        .map(char::from)
+}
+
+impl<C: Cell> AsciiOutput<C> {
+    pub fn push(&mut self, value: C) {
+        match to_ascii(&value) {
+            Some(c) => self.text.push(c),
+            None => self.values.push(value),
+        }
+    }
+}
+
+impl<C: Cell> Machine<C> {
+    /// Queues `line` as character codes followed by a newline. A trailing
+    /// newline in `line` is not doubled. A line with any character outside
+    /// the ASCII range is an error, and nothing is queued.
+    pub fn input_line(&mut self, line: &str) -> Result<(), IntcodeError> {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if let Some((position, character)) = line.chars().enumerate().find(|(_, c)| !c.is_ascii()) {
+            return Err(IntcodeError::NonAsciiInput {
+                character,
+                position,
+            });
+        }
+
+        for c in line.chars().chain(Some('\n')) {
+            self.input(C::from_isize(c as isize));
+        }
+        Ok(())
+    }
+
+    /// Takes every output collected by `run` and splits it into text and
+    /// values outside the ASCII range
+    pub fn ascii_output(&mut self) -> AsciiOutput<C> {
+        let mut output = AsciiOutput::default();
+        for value in self.drain_output() {
+            output.push(value);
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+fn echo() -> Vec<isize> {
+    // Echoes one line, then outputs 12345
+    crate::asm::assemble(
+        "
+        loop:   IN c
+                OUT c
+                EQ c, #10, done
+                JIF done, #loop
+                OUT #12345
+                HALT
+        c:      DATA 0
+        done:   DATA 0
+        ",
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_ascii() {
+    let mut m = Machine::default();
+    m.init(&echo());
+    m.input_line("Hello, world!\n").unwrap();
+    assert_eq!(m.run(), Ok(Some(12345)));
+
+    assert_eq!(
+        m.ascii_output(),
+        AsciiOutput {
+            text: "Hello, world!\n".to_string(),
+            values: vec![12345],
+        }
+    );
+    assert_eq!(m.ascii_output(), AsciiOutput::default());
+
+    m.init(&echo());
+    m.input_line("NOT A\r\n").unwrap();
+    m.run().unwrap();
+    assert_eq!(m.ascii_output().text, "NOT A\n");
+
+    m.init(&echo());
+    assert_eq!(
+        m.input_line("naïve"),
+        Err(IntcodeError::NonAsciiInput {
+            character: 'ï',
+            position: 2,
+        })
+    );
+    assert!(m.register().pending_input().is_empty());
+}
+
+#[test]
+fn test_to_ascii() {
+    assert_eq!(to_ascii(&65isize), Some('A'));
+    assert_eq!(to_ascii(&10isize), Some('\n'));
+    assert_eq!(to_ascii(&128isize), None);
+    assert_eq!(to_ascii(&-1isize), None);
+    assert_eq!(to_ascii(&crate::BigInt::from(10).pow(30)), None);
+}
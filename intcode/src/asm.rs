@@ -0,0 +1,272 @@
+// Assembles the listing format produced by `disasm` back into a program.
+//
+//         IN n                ; comments run to the end of the line
+// loop:   OUT n
+//         ADD n, #-1, n       ; '#' marks immediate, '@' relative parameters
+//         JIT n, #loop        ; labels resolve to addresses
+//         HALT
+// n:      DATA 0              ; raw values, comma separated
+//
+// Leading numeric addresses such as "   12:" in listings are ignored.
+
+use crate::{InstructionSet, IntcodeError};
+use std::collections::HashMap;
+
+fn error(line: usize, message: String) -> IntcodeError {
+    IntcodeError::Assemble { line, message }
+}
+
+fn is_label(name: &str) -> bool {
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn value(token: &str, labels: &HashMap<&str, usize>, line: usize) -> Result<isize, IntcodeError> {
+    if let Ok(val) = token.parse::<isize>() {
+        return Ok(val);
+    }
+
+    // A label, optionally followed by an offset as in "table+2"
+    let (name, offset) = match token.find(['+', '-']) {
+        Some(i) => match token[i..].parse::<isize>() {
+            Ok(offset) => (token[..i].trim(), offset),
+            Err(_) => return Err(error(line, format!("Invalid offset in {:?}", token))),
+        },
+        None => (token, 0),
+    };
+
+    if !is_label(name) {
+        return Err(error(line, format!("Invalid value {:?}", token)));
+    }
+
+    let address = labels
+        .get(name)
+        .ok_or_else(|| error(line, format!("Undefined label {:?}", name)))?;
+    (*address as isize)
+        .checked_add(offset)
+        .ok_or_else(|| error(line, format!("Address out of range in {:?}", token)))
+}
+
+/// Parameter mode digit and value of an operand
+fn operand(
+    token: &str,
+    labels: &HashMap<&str, usize>,
+    line: usize,
+) -> Result<(isize, isize), IntcodeError> {
+    if let Some(token) = token.strip_prefix('#') {
+        Ok((1, value(token, labels, line)?))
+    } else if let Some(token) = token.strip_prefix('@') {
+        Ok((2, value(token, labels, line)?))
+    } else {
+        Ok((0, value(token, labels, line)?))
+    }
+}
+
+struct Statement<'a> {
+    line: usize,
+    // None for data
+    code: Option<isize>,
+    operands: Vec<&'a str>,
+}
+
+pub fn assemble(source: &str) -> Result<Vec<isize>, IntcodeError> {
+    let instruction_set: InstructionSet = InstructionSet::default();
+
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = 0;
+
+    // First pass: collect labels and the size of every statement
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let mut text = text.split(';').next().unwrap_or("").trim();
+
+        while let Some(colon) = text.find(':') {
+            let name = text[..colon].trim();
+            if is_label(name) {
+                if labels.insert(name, address).is_some() {
+                    return Err(error(line, format!("Duplicate label {:?}", name)));
+                }
+            } else if name.is_empty() || !name.chars().all(|c| c.is_ascii_digit()) {
+                return Err(error(line, format!("Invalid label {:?}", name)));
+            }
+            text = text[colon + 1..].trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = match text.find(char::is_whitespace) {
+            Some(i) => (&text[..i], text[i..].trim()),
+            None => (text, ""),
+        };
+
+        let operands: Vec<&str> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(str::trim).collect()
+        };
+
+        if mnemonic.eq_ignore_ascii_case("DATA") {
+            if operands.is_empty() {
+                return Err(error(line, "DATA needs at least one value".to_string()));
+            }
+            address += operands.len();
+            statements.push(Statement {
+                line,
+                code: None,
+                operands,
+            });
+        } else {
+            let (code, arg_len) = instruction_set
+                .lookup(mnemonic)
+                .ok_or_else(|| error(line, format!("Unknown mnemonic {:?}", mnemonic)))?;
+            if operands.len() != arg_len {
+                return Err(error(
+                    line,
+                    format!("{} takes {} arguments", mnemonic, arg_len),
+                ));
+            }
+            address += arg_len + 1;
+            statements.push(Statement {
+                line,
+                code: Some(code),
+                operands,
+            });
+        }
+    }
+
+    // Second pass: emit code with every label resolved
+    let mut program = Vec::with_capacity(address);
+
+    for statement in statements {
+        match statement.code {
+            Some(code) => {
+                let mut params = Vec::with_capacity(statement.operands.len());
+                let mut instruction = code;
+                for (i, token) in statement.operands.iter().enumerate() {
+                    let (mode, val) = operand(token, &labels, statement.line)?;
+                    instruction += mode * 100 * 10_isize.pow(i as u32);
+                    params.push(val);
+                }
+                program.push(instruction);
+                program.extend(params);
+            }
+            None => {
+                for token in statement.operands {
+                    program.push(value(token, &labels, statement.line)?);
+                }
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+#[test]
+fn test_assemble() {
+    let program = assemble(
+        "
+        ; Count down from the input to 1
+                IN n
+        loop:   OUT n
+                ADD n, #-1, n
+                JIT n, #loop
+                HALT
+        n:      DATA 0
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(
+        program,
+        vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]
+    );
+
+    let mut m = crate::Machine::default();
+    m.init(&program);
+    m.input(3);
+    m.run().unwrap();
+
+    assert_eq!(m.drain_output(), vec![3, 2, 1]);
+}
+
+#[test]
+fn test_assemble_relative_and_offsets() {
+    let program = assemble(
+        "
+                ARB #table
+                OUT @1
+                OUT table+2
+                HALT
+        table:  DATA 10, 20, 30
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(program, vec![109, 7, 204, 1, 4, 9, 99, 10, 20, 30]);
+}
+
+#[test]
+fn test_assemble_listing_round_trip() {
+    let p = [
+        3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0,
+        1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20,
+        1105, 1, 46, 98, 99,
+    ];
+    assert_eq!(assemble(&crate::disasm::listing(&p)), Ok(p.to_vec()));
+
+    let p = crate::read_program("test.txt").unwrap();
+    assert_eq!(assemble(&crate::disasm::listing(&p)), Ok(p));
+}
+
+#[test]
+fn test_assemble_errors() {
+    assert_eq!(
+        assemble("HALT\nFOO 1"),
+        Err(IntcodeError::Assemble {
+            line: 2,
+            message: "Unknown mnemonic \"FOO\"".to_string()
+        })
+    );
+    assert_eq!(
+        assemble("ADD 1, 2"),
+        Err(IntcodeError::Assemble {
+            line: 1,
+            message: "ADD takes 3 arguments".to_string()
+        })
+    );
+    assert_eq!(
+        assemble("JIT #1, #nowhere"),
+        Err(IntcodeError::Assemble {
+            line: 1,
+            message: "Undefined label \"nowhere\"".to_string()
+        })
+    );
+    assert_eq!(
+        assemble("a: HALT\na: HALT"),
+        Err(IntcodeError::Assemble {
+            line: 2,
+            message: "Duplicate label \"a\"".to_string()
+        })
+    );
+    assert_eq!(
+        assemble("x: HALT\nDATA x+18446744073709551615"),
+        Err(IntcodeError::Assemble {
+            line: 2,
+            message: "Invalid offset in \"x+18446744073709551615\"".to_string()
+        })
+    );
+    assert_eq!(
+        assemble("HALT\nx: DATA x+9223372036854775807"),
+        Err(IntcodeError::Assemble {
+            line: 2,
+            message: "Address out of range in \"x+9223372036854775807\"".to_string()
+        })
+    );
+}
@@ -0,0 +1,291 @@
+use intcode::ascii::to_ascii;
+use intcode::{Machine, Status};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::process;
+
+const USAGE: &str = "Usage: intcode-ascii <program> [--script <file>] [--log <file>]";
+
+const HELP: &str = "\
+Lines are sent to the program as ASCII input, except for these commands:
+  !save <file>        save the machine to file
+  !load <file>        restore a machine saved with !save
+  !quit               exit";
+
+/// Connects a machine to a terminal. Everything shown on `out` and every
+/// line entered is also written to `log`.
+struct Console<O, L> {
+    m: Machine,
+    out: O,
+    log: Option<L>,
+    halted: bool,
+    // Whether the last text shown ended a line
+    line_start: bool,
+}
+
+impl<O: Write, L: Write> Console<O, L> {
+    fn new(m: Machine, out: O, log: Option<L>) -> Console<O, L> {
+        Console {
+            m,
+            out,
+            log,
+            halted: false,
+            line_start: true,
+        }
+    }
+
+    fn show(&mut self, text: &str) -> io::Result<()> {
+        self.out.write_all(text.as_bytes())?;
+        if let Some(log) = self.log.as_mut() {
+            log.write_all(text.as_bytes())?;
+        }
+        if let Some(c) = text.chars().last() {
+            self.line_start = c == '\n';
+        }
+        Ok(())
+    }
+
+    /// Shows a line of its own, even if the program left a line unfinished
+    fn message(&mut self, text: &str) -> io::Result<()> {
+        if !self.line_start {
+            self.show("\n")?;
+        }
+        self.show(&format!("{}\n", text))
+    }
+
+    /// Runs until the program waits for input or halts, showing its output
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            match self.m.resume() {
+                Ok(Status::Output(value)) => match to_ascii(&value) {
+                    Some(c) => self.show(c.encode_utf8(&mut [0; 4]))?,
+                    None => self.message(&value.to_string())?,
+                },
+                Ok(Status::NeedInput) => break,
+                Ok(Status::Halted) => {
+                    self.halted = true;
+                    self.message("Halted")?;
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.halted = true;
+                    self.message(&format!("Error: {}", e))?;
+                    break;
+                }
+            }
+        }
+
+        self.out.flush()?;
+        if let Some(log) = self.log.as_mut() {
+            log.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Handles one line of input. Lines read from a script are echoed, as
+    /// nobody typed them. Returns false when the console should exit.
+    fn line(&mut self, line: &str, echo: bool) -> io::Result<bool> {
+        if echo {
+            self.show(&format!("{}\n", line))?;
+        } else if let Some(log) = self.log.as_mut() {
+            writeln!(log, "{}", line)?;
+        }
+
+        if !line.starts_with('!') {
+            if self.halted {
+                self.message("Error: The program has halted (try !load or !quit)")?;
+            } else {
+                match self.m.input_line(line) {
+                    Ok(()) => self.run()?,
+                    Err(e) => self.message(&format!("Error: {}", e))?,
+                }
+            }
+            return Ok(true);
+        }
+
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("!quit"), _) => return Ok(false),
+            (Some("!help"), _) => self.message(HELP)?,
+            (Some("!save"), Some(path)) => match self.m.save_snapshot(path) {
+                Ok(()) => self.message(&format!("Saved to {}", path))?,
+                Err(e) => self.message(&format!("Error: {}", e))?,
+            },
+            (Some("!load"), Some(path)) => match self.m.load_snapshot(path) {
+                Ok(()) => {
+                    self.halted = false;
+                    self.message(&format!("Loaded {}", path))?;
+                    self.run()?;
+                }
+                Err(e) => self.message(&format!("Error: {}", e))?,
+            },
+            (Some("!save"), None) | (Some("!load"), None) => {
+                self.message("Error: Missing file name")?
+            }
+            (Some(other), _) => {
+                self.message(&format!("Error: Unknown command: {} (try !help)", other))?
+            }
+            (None, _) => {}
+        }
+
+        Ok(true)
+    }
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut args = env::args().skip(1);
+    let mut program = None;
+    let mut script = None;
+    let mut log = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--script" => script = Some(args.next().unwrap_or_else(|| exit(USAGE))),
+            "--log" => log = Some(args.next().unwrap_or_else(|| exit(USAGE))),
+            _ if program.is_none() => program = Some(arg),
+            _ => exit(USAGE),
+        }
+    }
+
+    let mut m = Machine::default();
+    if let Err(e) = m.load_program(&program.unwrap_or_else(|| exit(USAGE))) {
+        exit(&e.to_string());
+    }
+
+    let log = log.map(|path| File::create(path).unwrap_or_else(|e| exit(&e.to_string())));
+    let mut console = Console::new(m, io::stdout(), log);
+    console.run()?;
+
+    // A script is played before handing over to stdin
+    if let Some(path) = script {
+        let script = fs::read_to_string(path).unwrap_or_else(|e| exit(&e.to_string()));
+        for line in script.lines() {
+            if !console.line(line, true)? {
+                return Ok(());
+            }
+        }
+    }
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        if !console.line(&line?, false)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn session(lines: &[&str], echo: bool) -> (String, String) {
+    // Echoes each line after a prompt, outputs 1000 and halts on a '.'
+    let program = intcode::asm::assemble(
+        "
+        prompt: OUT #62
+                OUT #10
+        read:   IN c
+                EQ c, #46, t
+                JIT t, #done
+                OUT c
+                EQ c, #10, t
+                JIT t, #prompt
+                JIT #1, #read
+        done:   OUT #1000
+                HALT
+        c:      DATA 0
+        t:      DATA 0
+        ",
+    )
+    .unwrap();
+
+    let mut m = Machine::default();
+    m.init(&program);
+
+    let mut console = Console::new(m, Vec::new(), Some(Vec::new()));
+    console.run().unwrap();
+    for line in lines {
+        if !console.line(line, echo).unwrap() {
+            break;
+        }
+    }
+
+    let out = String::from_utf8(console.out).unwrap();
+    let log = String::from_utf8(console.log.unwrap()).unwrap();
+    (out, log)
+}
+
+#[test]
+fn test_session() {
+    let (out, log) = session(
+        &["hi", "né", ".", "more", "!bogus", "!quit", "never"],
+        false,
+    );
+
+    assert_eq!(
+        out,
+        ">\n\
+         hi\n\
+         >\n\
+         Error: Input 'é' at position 1 is not ASCII\n\
+         1000\n\
+         Halted\n\
+         Error: The program has halted (try !load or !quit)\n\
+         Error: Unknown command: !bogus (try !help)\n"
+    );
+    assert_eq!(
+        log,
+        ">\n\
+         hi\n\
+         hi\n\
+         >\n\
+         né\n\
+         Error: Input 'é' at position 1 is not ASCII\n\
+         .\n\
+         1000\n\
+         Halted\n\
+         more\n\
+         Error: The program has halted (try !load or !quit)\n\
+         !bogus\n\
+         Error: Unknown command: !bogus (try !help)\n\
+         !quit\n"
+    );
+}
+
+#[test]
+fn test_save_and_load() {
+    let path = env::temp_dir().join("intcode_test_ascii.txt");
+    let path = path.to_str().unwrap();
+
+    let save = format!("!save {}", path);
+    let load = format!("!load {}", path);
+    let (out, log) = session(&["a", &save, ".", &load, "b"], true);
+
+    let expected = format!(
+        ">\n\
+         a\n\
+         a\n\
+         >\n\
+         {}\n\
+         Saved to {}\n\
+         .\n\
+         1000\n\
+         Halted\n\
+         {}\n\
+         Loaded {}\n\
+         b\n\
+         b\n\
+         >\n",
+        save, path, load, path
+    );
+    assert_eq!(out, expected);
+    assert_eq!(log, expected);
+
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,283 @@
+use intcode::{Machine, Status};
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::str::FromStr;
+
+const HELP: &str = "\
+Commands:
+  step [n]            execute n instructions (default 1)
+  continue            run until a breakpoint, watchpoint, input wait or halt
+  break <addr>        stop before executing the instruction at addr
+  delete <addr>       remove a breakpoint
+  watch <addr>        stop before any instruction that reads or writes addr
+  unwatch <addr>      remove a watchpoint
+  mem <addr> [len]    show len memory cells starting at addr (default 1)
+  regs                show the registers
+  input <n>...        queue input values
+  disasm [addr] [n]   disassemble n instructions from addr (default ip, 10)
+  quit                exit the debugger";
+
+// Most cells or instructions shown by one mem or disasm command
+const MAX_COUNT: usize = 10_000;
+
+enum CommandError {
+    Io(io::Error),
+    Usage(String),
+}
+
+impl From<io::Error> for CommandError {
+    fn from(e: io::Error) -> CommandError {
+        CommandError::Io(e)
+    }
+}
+
+fn parse<T: FromStr>(arg: Option<&str>, default: Option<T>) -> Result<T, CommandError> {
+    match arg {
+        Some(arg) => arg
+            .parse::<T>()
+            .map_err(|_| CommandError::Usage(format!("Invalid number: {}", arg))),
+        None => default.ok_or_else(|| CommandError::Usage("Missing argument".to_string())),
+    }
+}
+
+fn count(arg: Option<&str>, default: usize) -> Result<usize, CommandError> {
+    let count = parse(arg, Some(default))?;
+    if count > MAX_COUNT {
+        return Err(CommandError::Usage(format!(
+            "Count {} is more than {}",
+            count, MAX_COUNT
+        )));
+    }
+    Ok(count)
+}
+
+fn show_next(m: &Machine, out: &mut impl Write) -> io::Result<()> {
+    let ip = m.register().instruction_pointer();
+    for line in m.disassemble(ip, 1) {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn show_status(status: &Status, out: &mut impl Write) -> io::Result<()> {
+    match status {
+        Status::Output(output) => writeln!(out, "Output: {}", output),
+        Status::NeedInput => writeln!(out, "Waiting for input"),
+        Status::Halted => writeln!(out, "Halted"),
+        Status::Stepped => Ok(()),
+        Status::Breakpoint(address) => writeln!(out, "Breakpoint at {}", address),
+        Status::Watchpoint { address, access } => {
+            writeln!(out, "Watchpoint: {:?} of {}", access, address)
+        }
+    }
+}
+
+fn execute(m: &mut Machine, line: &str, out: &mut impl Write) -> Result<(), CommandError> {
+    let mut words = line.split_whitespace();
+
+    let run_error = |e: intcode::IntcodeError| CommandError::Usage(e.to_string());
+
+    match words.next() {
+        None => {}
+        Some("help") | Some("h") => writeln!(out, "{}", HELP)?,
+        Some("step") | Some("s") => {
+            let n: usize = parse(words.next(), Some(1))?;
+            for _ in 0..n {
+                let status = m.step().map_err(run_error)?;
+                show_status(&status, out)?;
+                match status {
+                    Status::Stepped | Status::Output(_) => {}
+                    _ => break,
+                }
+            }
+            show_next(m, out)?;
+        }
+        Some("continue") | Some("c") => loop {
+            let status = m.resume().map_err(run_error)?;
+            show_status(&status, out)?;
+            if let Status::Output(_) = status {
+                continue;
+            }
+            show_next(m, out)?;
+            break;
+        },
+        Some("break") | Some("b") => m.add_breakpoint(parse(words.next(), None)?),
+        Some("delete") | Some("d") => {
+            let address = parse(words.next(), None)?;
+            if !m.remove_breakpoint(address) {
+                return Err(CommandError::Usage(format!("No breakpoint at {}", address)));
+            }
+        }
+        Some("watch") | Some("w") => m.add_watchpoint(parse(words.next(), None)?),
+        Some("unwatch") => {
+            let address = parse(words.next(), None)?;
+            if !m.remove_watchpoint(address) {
+                return Err(CommandError::Usage(format!("No watchpoint at {}", address)));
+            }
+        }
+        Some("mem") | Some("m") => {
+            let address: usize = parse(words.next(), None)?;
+            let len = count(words.next(), 1)?;
+            let end = address
+                .checked_add(len)
+                .ok_or_else(|| CommandError::Usage(format!("Address {} out of range", address)))?;
+            let cells: Vec<String> = (address..end)
+                .map(|loc| m.memory.get(loc).to_string())
+                .collect();
+            writeln!(out, "{:>5}: {}", address, cells.join(","))?;
+        }
+        Some("regs") | Some("r") => {
+            let r = m.register();
+            writeln!(
+                out,
+                "ip={} rb={} carry={} sign={} input={:?}",
+                r.instruction_pointer(),
+                r.relative_base(),
+                r.carry_flag_set() as u8,
+                r.sign_flag_set() as u8,
+                r.pending_input()
+            )?;
+        }
+        Some("input") | Some("i") => {
+            for word in words {
+                m.input(parse(Some(word), None)?);
+            }
+        }
+        Some("disasm") | Some("l") => {
+            let address = parse(words.next(), Some(m.register().instruction_pointer()))?;
+            let count = count(words.next(), 10)?;
+            for line in m.disassemble(address, count) {
+                writeln!(out, "{}", line)?;
+            }
+        }
+        Some(other) => {
+            return Err(CommandError::Usage(format!(
+                "Unknown command: {} (try help)",
+                other
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+/// Executes one debugger command. Returns false when the debugger should exit.
+fn command(m: &mut Machine, line: &str, out: &mut impl Write) -> io::Result<bool> {
+    if let Some("quit") | Some("q") = line.split_whitespace().next() {
+        return Ok(false);
+    }
+
+    match execute(m, line, out) {
+        Ok(()) => Ok(true),
+        Err(CommandError::Usage(message)) => {
+            writeln!(out, "Error: {}", message)?;
+            Ok(true)
+        }
+        Err(CommandError::Io(e)) => Err(e),
+    }
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: intcode-dbg <program> [input...]");
+        process::exit(1);
+    }
+
+    let mut m = Machine::default();
+    if let Err(e) = m.load_program(&args[1]) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+
+    let mut out = io::stdout();
+
+    command(&mut m, &format!("input {}", args[2..].join(" ")), &mut out)?;
+    show_next(&m, &mut out)?;
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        write!(out, "(dbg) ")?;
+        out.flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+
+        if !command(&mut m, &line, &mut out)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn session(program: &[isize], commands: &[&str]) -> String {
+    let mut m = Machine::default();
+    m.init(program);
+
+    let mut out = Vec::new();
+    for line in commands {
+        command(&mut m, line, &mut out).unwrap();
+    }
+
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_break_and_continue() {
+    let out = session(
+        &[3, 9, 1001, 9, 1, 10, 4, 10, 99, 0, 0],
+        &["break 6", "input 41", "continue", "mem 9 2", "continue"],
+    );
+
+    assert_eq!(
+        out,
+        "Breakpoint at 6\n\
+         \x20   6: OUT 10                   ; 4,10\n\
+         \x20   9: 41,42\n\
+         Output: 42\n\
+         Halted\n\
+         \x20   8: HALT                     ; 99\n"
+    );
+}
+
+#[test]
+fn test_large_arguments() {
+    let out = session(
+        &[99],
+        &[
+            "mem 18446744073709551615 2",
+            "mem 0 4611686018427387904",
+            "disasm 0 4611686018427387904",
+            "disasm 18446744073709551615 1",
+        ],
+    );
+
+    assert_eq!(
+        out,
+        "Error: Address 18446744073709551615 out of range\n\
+         Error: Count 4611686018427387904 is more than 10000\n\
+         Error: Count 4611686018427387904 is more than 10000\n\
+         18446744073709551615: DATA 0                   ; 0\n"
+    );
+}
+
+#[test]
+fn test_step_and_regs() {
+    let out = session(&[109, 7, 3, 0, 99], &["step", "regs", "step", "bogus"]);
+
+    assert_eq!(
+        out,
+        "    2: IN 0                     ; 3,0\n\
+         ip=2 rb=7 carry=0 sign=0 input=[]\n\
+         Waiting for input\n\
+         \x20   2: IN 0                     ; 3,0\n\
+         Error: Unknown command: bogus (try help)\n"
+    );
+}
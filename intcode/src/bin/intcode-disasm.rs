@@ -0,0 +1,26 @@
+use intcode::{cfg, disasm, read_program};
+use std::env;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    // With --dot, prints the control flow graph instead of a listing
+    let (dot, path) = match args.as_slice() {
+        [path] => (false, path),
+        [flag, path] if flag == "--dot" => (true, path),
+        _ => {
+            eprintln!("Usage: intcode-disasm [--dot] <program>");
+            process::exit(1);
+        }
+    };
+
+    match read_program(path) {
+        Ok(program) if dot => print!("{}", cfg::control_flow(&program).dot()),
+        Ok(program) => print!("{}", disasm::listing(&program)),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,353 @@
+// Machines can be given op codes beyond the standard set, for example to
+// print debugging information or call into the host. A custom instruction
+// decodes like any other, with parameter modes, and its handler then gets a
+// `Context` to read and write its arguments, take input, jump or halt.
+
+use std::sync::Arc;
+
+use crate::io::Io;
+use crate::{
+    Call, Cell, Instruction, InstructionSet, IntcodeError, Machine, Memory, OpCode, Operation,
+    Register, MAX_ARGS, OP_CODES,
+};
+
+/// Handler for a custom op code. Returning a value outputs it, as OUT does.
+pub type Handler<C = isize> =
+    Arc<dyn Fn(&mut Context<C>) -> Result<Option<C>, IntcodeError> + Send + Sync>;
+
+/// The machine state a custom instruction can see and change
+pub struct Context<'a, C = isize> {
+    memory: &'a mut Memory<C>,
+    register: &'a mut Register<C>,
+    io: &'a mut Io<C>,
+    instruction: &'a Instruction,
+}
+
+pub struct MachineBuilder<C: Cell = isize> {
+    instruction_set: InstructionSet<C>,
+}
+
+impl<'a, C: Cell> Context<'a, C> {
+    pub(crate) fn new(
+        memory: &'a mut Memory<C>,
+        register: &'a mut Register<C>,
+        io: &'a mut Io<C>,
+        instruction: &'a Instruction,
+    ) -> Context<'a, C> {
+        Context {
+            memory,
+            register,
+            io,
+            instruction,
+        }
+    }
+
+    pub fn instruction(&self) -> &Instruction {
+        self.instruction
+    }
+
+    /// Value of parameter `i`, read according to its mode
+    pub fn arg(&self, i: usize) -> C {
+        self.memory.get(self.instruction.args()[i])
+    }
+
+    /// Writes `value` to parameter `i`
+    pub fn set_arg(&mut self, i: usize, value: C) {
+        self.memory.set(self.instruction.args()[i], value)
+    }
+
+    pub fn read(&self, address: usize) -> C {
+        self.memory.get(address)
+    }
+
+    pub fn write(&mut self, address: usize, value: C) {
+        self.memory.set(address, value)
+    }
+
+    pub fn register(&self) -> &Register<C> {
+        self.register
+    }
+
+    /// Takes the next input, from the queue or the input source
+    pub fn input(&mut self) -> Option<C> {
+        self.register.get_input().or_else(|| self.io.read())
+    }
+
+    /// Stops the machine with `Status::NeedInput`. The instruction runs again
+    /// once the machine is resumed.
+    pub fn wait_for_input(&mut self) {
+        self.register.set_wait_flag();
+    }
+
+    /// Continues at `address` instead of the next instruction
+    pub fn jump(&mut self, address: usize) {
+        self.register.set_instruction_pointer(address);
+        self.register.set_jump_flag();
+    }
+
+    pub fn halt(&mut self) {
+        self.register.set_halt_flag();
+    }
+
+    /// An error stopping the machine at this instruction
+    pub fn error(&self, message: impl Into<String>) -> IntcodeError {
+        IntcodeError::Handler {
+            instruction_pointer: self.register.instruction_pointer,
+            code: self.memory.code(self.register.instruction_pointer),
+            message: message.into(),
+        }
+    }
+}
+
+impl Default for MachineBuilder {
+    fn default() -> MachineBuilder {
+        MachineBuilder::new()
+    }
+}
+
+impl<C: Cell> MachineBuilder<C> {
+    /// A builder starting from the standard instruction set
+    pub fn new() -> MachineBuilder<C> {
+        MachineBuilder {
+            instruction_set: InstructionSet::default(),
+        }
+    }
+
+    /// Adds op code `code`, taking `arg_len` parameters and written as `name`
+    /// in traces, profiles and the machine's own listings such as
+    /// `Machine::disassemble`. Names are letters, digits and underscores, and
+    /// `DATA` is reserved. Watchpoints and traces treat every parameter of a
+    /// custom instruction as read.
+    pub fn opcode(
+        mut self,
+        code: isize,
+        name: &'static str,
+        arg_len: usize,
+        handler: impl Fn(&mut Context<C>) -> Result<Option<C>, IntcodeError> + Send + Sync + 'static,
+    ) -> Result<MachineBuilder<C>, IntcodeError> {
+        let invalid = |reason: String| Err(IntcodeError::InvalidOpCode { code, reason });
+
+        if code < 1 || code >= OP_CODES as isize {
+            return invalid(format!("Op codes are from 1 to {}", OP_CODES - 1));
+        }
+        if arg_len > MAX_ARGS {
+            return invalid(format!("At most {} parameters are supported", MAX_ARGS));
+        }
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return invalid(format!("Invalid name {:?}", name));
+        }
+        if name.eq_ignore_ascii_case("DATA") {
+            return invalid("DATA is reserved".to_string());
+        }
+        if let Some(entry) = &self.instruction_set.op_codes[code as usize] {
+            return Err(IntcodeError::OpCodeConflict {
+                code,
+                operation: entry.operation.to_string(),
+            });
+        }
+        if let Some((other, _)) = self.instruction_set.lookup(name) {
+            return invalid(format!("{} is already the name of op code {}", name, other));
+        }
+
+        self.instruction_set.op_codes[code as usize] = Some(OpCode {
+            operation: Operation::Custom(name),
+            arg_len,
+            call: Call::Custom(Arc::new(handler)),
+        });
+        Ok(self)
+    }
+
+    pub fn build(self) -> Machine<C> {
+        Machine {
+            instruction_set: self.instruction_set,
+            ..Machine::new()
+        }
+    }
+}
+
+#[test]
+fn test_custom_opcodes() {
+    use crate::Status;
+    use std::sync::Mutex;
+
+    let printed = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&printed);
+
+    let mut m = Machine::builder()
+        // Prints its parameter
+        .opcode(42, "DBG", 1, move |c| {
+            log.lock().unwrap().push(c.arg(0));
+            Ok(None)
+        })
+        .unwrap()
+        // Squares its first parameter into its second and outputs the result
+        .opcode(50, "SQR", 2, |c| {
+            let square = c.arg(0) * c.arg(0);
+            c.set_arg(1, square);
+            Ok(Some(square))
+        })
+        .unwrap()
+        // Adds up input until it reads a zero
+        .opcode(51, "SUM", 1, |c| loop {
+            match c.input() {
+                Some(0) => return Ok(None),
+                Some(v) => {
+                    let sum = c.arg(0) + v;
+                    c.set_arg(0, sum);
+                }
+                None => {
+                    c.wait_for_input();
+                    return Ok(None);
+                }
+            }
+        })
+        .unwrap()
+        .build();
+
+    m.init(&[142, 7, 150, 3, 11, 51, 11, 4, 11, 99, 0, 0]);
+    m.input(1);
+    m.input(2);
+
+    assert_eq!(m.resume(), Ok(Status::Output(9)));
+    assert_eq!(*printed.lock().unwrap(), vec![7]);
+    assert_eq!(m.memory.get(11), 9);
+
+    assert_eq!(m.resume(), Ok(Status::NeedInput));
+    m.input(3);
+    m.input(0);
+    assert_eq!(m.resume(), Ok(Status::Output(15)));
+    assert_eq!(m.resume(), Ok(Status::Halted));
+}
+
+#[test]
+fn test_custom_control_flow() {
+    let mut m = Machine::builder()
+        // Jumps to its parameter
+        .opcode(60, "JMP", 1, |c| {
+            let target = c.arg(0);
+            if target < 0 {
+                return Err(c.error("negative jump target"));
+            }
+            c.jump(target as usize);
+            Ok(None)
+        })
+        .unwrap()
+        .opcode(61, "STOP", 0, |c| {
+            c.halt();
+            Ok(None)
+        })
+        .unwrap()
+        .build();
+
+    m.init(&[160, 4, 104, 1, 104, 2, 61]);
+    assert_eq!(m.run(), Ok(Some(2)));
+
+    m.init(&[160, -1]);
+    assert_eq!(
+        m.run(),
+        Err(IntcodeError::Handler {
+            instruction_pointer: 0,
+            code: 160,
+            message: "negative jump target".to_string(),
+        })
+    );
+}
+
+#[test]
+fn test_opcode_errors() {
+    let ok = |_: &mut Context| Ok(None);
+
+    assert_eq!(
+        Machine::builder().opcode(1, "FOO", 0, ok).err(),
+        Some(IntcodeError::OpCodeConflict {
+            code: 1,
+            operation: "ADD".to_string(),
+        })
+    );
+    assert_eq!(
+        Machine::builder()
+            .opcode(40, "FOO", 0, ok)
+            .unwrap()
+            .opcode(40, "BAR", 0, ok)
+            .err(),
+        Some(IntcodeError::OpCodeConflict {
+            code: 40,
+            operation: "FOO".to_string(),
+        })
+    );
+    assert!(matches!(
+        Machine::builder().opcode(100, "FOO", 0, ok),
+        Err(IntcodeError::InvalidOpCode { code: 100, .. })
+    ));
+    assert!(matches!(
+        Machine::builder().opcode(0, "FOO", 0, ok),
+        Err(IntcodeError::InvalidOpCode { code: 0, .. })
+    ));
+    assert!(matches!(
+        Machine::builder().opcode(40, "FOO", 4, ok),
+        Err(IntcodeError::InvalidOpCode { code: 40, .. })
+    ));
+    assert!(matches!(
+        Machine::builder().opcode(40, "add", 0, ok),
+        Err(IntcodeError::InvalidOpCode { code: 40, .. })
+    ));
+
+    for name in &["", "MY OP", "OP\n", "A,B"] {
+        assert_eq!(
+            Machine::builder().opcode(40, name, 0, ok).err(),
+            Some(IntcodeError::InvalidOpCode {
+                code: 40,
+                reason: format!("Invalid name {:?}", name),
+            })
+        );
+    }
+    assert_eq!(
+        Machine::builder().opcode(40, "data", 0, ok).err(),
+        Some(IntcodeError::InvalidOpCode {
+            code: 40,
+            reason: "DATA is reserved".to_string(),
+        })
+    );
+}
+
+#[test]
+fn test_custom_opcode_trace() {
+    let mut m = Machine::builder()
+        .opcode(42, "DBG", 1, |_| Ok(None))
+        .unwrap()
+        .build();
+
+    m.init(&[142, 5, 99]);
+    let instruction = m.next_instruction().unwrap();
+    assert_eq!(instruction.operation, Operation::Custom("DBG"));
+    assert_eq!(instruction.operation.to_string(), "DBG");
+    assert_eq!(instruction.args(), &[1]);
+
+    // Clones keep their custom op codes
+    assert_eq!(m.clone().run(), Ok(None));
+}
+
+#[test]
+fn test_custom_opcode_listings() {
+    let mut m = Machine::builder()
+        .opcode(42, "DBG", 1, |_| Ok(None))
+        .unwrap()
+        .build();
+
+    m.init(&[142, 5, 104, 1, 99, 0]);
+    let text: Vec<String> = m.disassemble(0, 3).into_iter().map(|l| l.text).collect();
+    assert_eq!(text, vec!["DBG #5", "OUT #1", "HALT"]);
+
+    m.start_profile();
+    m.run().unwrap();
+    let report = m.profile().unwrap().report(&m, 1);
+    assert!(report.contains("% DBG\n"));
+    assert!(report.ends_with("     0: DBG #5                   ; 142,5\n"));
+
+    let graph = m.control_flow();
+    assert_eq!(graph.blocks[&0].lines[0].text, "DBG #5");
+    assert_eq!(
+        graph.blocks[&0].exits,
+        vec![crate::cfg::Exit::Indirect, crate::cfg::Exit::Fallthrough(2)]
+    );
+}
@@ -0,0 +1,249 @@
+// Decoded instructions are cached by address so that loops skip decoding
+// after their first pass. Every write to memory drops the cached
+// instructions that cover the written cell, which keeps self-modifying
+// programs correct.
+
+use std::collections::HashMap;
+
+use crate::{Cell, Decoded, Instruction, IntcodeError, Machine, MAX_ARGS, PAGE_SIZE};
+
+#[derive(Clone)]
+pub(crate) struct DecodeCache<C> {
+    pages: HashMap<usize, Box<[Option<Decoded<C>>]>>,
+    // The first and last address anything has been cached at. Writes outside
+    // it, usually to data or the stack, are ignored without a lookup.
+    span: Option<(usize, usize)>,
+}
+
+impl<C> Default for DecodeCache<C> {
+    fn default() -> DecodeCache<C> {
+        DecodeCache {
+            pages: HashMap::new(),
+            span: None,
+        }
+    }
+}
+
+impl<C: Cell> DecodeCache<C> {
+    fn get(&self, address: usize) -> Option<&Decoded<C>> {
+        self.pages
+            .get(&(address / PAGE_SIZE))
+            .and_then(|page| page[address % PAGE_SIZE].as_ref())
+    }
+
+    fn insert(&mut self, address: usize, decoded: Decoded<C>) {
+        self.span = Some(match self.span {
+            Some((first, last)) => (first.min(address), last.max(address)),
+            None => (address, address),
+        });
+        let page = self
+            .pages
+            .entry(address / PAGE_SIZE)
+            .or_insert_with(|| vec![None; PAGE_SIZE].into_boxed_slice());
+        page[address % PAGE_SIZE] = Some(decoded);
+    }
+
+    /// Drops every cached instruction that covers `loc`. An instruction is
+    /// at most `MAX_ARGS + 1` cells long, so only those starting in the
+    /// `MAX_ARGS` cells before `loc` or at `loc` itself can.
+    pub(crate) fn invalidate(&mut self, loc: usize) {
+        let (first, last) = match self.span {
+            Some((first, last)) => (loc.saturating_sub(MAX_ARGS).max(first), loc.min(last)),
+            None => return,
+        };
+        if first > last {
+            return;
+        }
+
+        // The range crosses into a second page only near a page's start
+        for index in first / PAGE_SIZE..=last / PAGE_SIZE {
+            if let Some(page) = self.pages.get_mut(&index) {
+                let start = first.max(index * PAGE_SIZE);
+                let end = last.min(index * PAGE_SIZE + PAGE_SIZE - 1);
+                for address in start..=end {
+                    let entry = &mut page[address % PAGE_SIZE];
+                    if entry.as_ref().is_some_and(|d| address + d.arg_len >= loc) {
+                        *entry = None;
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.pages.clear();
+        self.span = None;
+    }
+}
+
+impl<C: Cell> Machine<C> {
+    /// Caches decoded instructions by address while `enabled`. Programs run
+    /// exactly as they do uncached, including ones that modify their own
+    /// code.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.memory.cache = if enabled {
+            Some(DecodeCache::default())
+        } else {
+            None
+        };
+    }
+
+    pub fn decode_cache_enabled(&self) -> bool {
+        self.memory.cache.is_some()
+    }
+
+    /// Decodes the instruction at the instruction pointer, from the cache
+    /// if it is enabled and holds one
+    pub(crate) fn fetch(&mut self) -> Result<Instruction, IntcodeError> {
+        let ip = self.register.instruction_pointer;
+
+        if let Some(decoded) = self.memory.cache.as_ref().and_then(|cache| cache.get(ip)) {
+            return decoded.resolve(&self.memory, &self.register);
+        }
+
+        let decoded = self
+            .instruction_set
+            .predecode(&self.memory, &self.register)?;
+        let instruction = decoded.resolve(&self.memory, &self.register);
+        if let Some(cache) = self.memory.cache.as_mut() {
+            cache.insert(ip, decoded);
+        }
+        instruction
+    }
+}
+
+// Runs `program` on a cached and an uncached machine side by side, one
+// instruction at a time, checking that they never diverge. Returns the
+// outputs.
+#[cfg(test)]
+fn run_both(program: &[isize], input: &[isize]) -> Vec<isize> {
+    use crate::Status;
+
+    let mut uncached = Machine::default();
+    let mut cached = Machine::default();
+    cached.set_decode_cache(true);
+
+    for m in [&mut uncached, &mut cached].iter_mut() {
+        m.init(program);
+        for i in input {
+            m.input(*i);
+        }
+    }
+
+    let mut outputs = Vec::new();
+    loop {
+        let status = uncached.step();
+        assert_eq!(cached.step(), status);
+        assert_eq!(
+            cached.register().instruction_pointer(),
+            uncached.register().instruction_pointer()
+        );
+        assert_eq!(
+            cached.register().relative_base(),
+            uncached.register().relative_base()
+        );
+
+        match status {
+            Ok(Status::Output(v)) => outputs.push(v),
+            Ok(Status::Stepped) => {}
+            _ => break,
+        }
+    }
+
+    assert_eq!(cached.memory.len(), uncached.memory.len());
+    for address in 0..uncached.memory.len() {
+        assert_eq!(cached.memory.get(address), uncached.memory.get(address));
+    }
+
+    outputs
+}
+
+#[test]
+fn test_cache_day2() {
+    run_both(&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50], &[]);
+    run_both(&[1, 1, 1, 4, 99, 5, 6, 0, 99], &[]);
+    run_both(&[2, 4, 4, 5, 99, 0], &[]);
+}
+
+#[test]
+fn test_cache_day5() {
+    let program = [
+        3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0,
+        1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20,
+        1105, 1, 46, 98, 99,
+    ];
+
+    assert_eq!(run_both(&program, &[7]), vec![999]);
+    assert_eq!(run_both(&program, &[8]), vec![1000]);
+    assert_eq!(run_both(&program, &[9]), vec![1001]);
+}
+
+#[test]
+fn test_cache_day9_quine() {
+    let program = [
+        109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+    ];
+
+    assert_eq!(run_both(&program, &[]), program.to_vec());
+}
+
+// The same instructions read and write a different cell on each pass as
+// the relative base moves through a table just past the code
+#[test]
+fn test_cache_relative_base() {
+    let program = crate::asm::assemble(
+        "
+                ARB #table
+        loop:   ADD @0, @1, @1
+                OUT @1
+                ARB #1
+                EQ @1, #0, t
+                JIF t, #loop
+                HALT
+        t:      DATA 0
+        table:  DATA 1, 2, 3, 4, 0
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(run_both(&program, &[]), vec![3, 6, 10]);
+}
+
+// A loop that rewrites both the op code and an operand of an instruction
+// it has already executed
+#[test]
+fn test_cache_self_modifying() {
+    let program = crate::asm::assemble(
+        "
+        patch:  ADD #3, #4, x
+                OUT x
+                JIT count, #end
+                ADD #1, #0, count
+                ADD #1102, #0, patch
+                ADD #5, #0, patch+2
+                JIF #0, #patch
+        end:    HALT
+        x:      DATA 0
+        count:  DATA 0
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(run_both(&program, &[]), vec![7, 15]);
+}
+
+#[test]
+fn test_cache_restore() {
+    let mut m = Machine::default();
+    m.set_decode_cache(true);
+    m.init(&[1101, 1, 2, 0, 99]);
+    let snapshot = m.snapshot();
+
+    assert_eq!(m.run(), Ok(None));
+    assert_eq!(m.memory.get(0), 3);
+
+    m.restore(&snapshot).unwrap();
+    assert!(m.decode_cache_enabled());
+    assert_eq!(m.run(), Ok(None));
+    assert_eq!(m.memory.get(0), 3);
+}
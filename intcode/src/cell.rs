@@ -0,0 +1,182 @@
+// The integer type a machine stores in memory and computes with. Machines
+// use `isize` unless another type is chosen when they are created, for
+// example `Machine::<i128>::new()` or, for programs that produce numbers
+// of any size, `Machine::<BigInt>::new()`.
+//
+// Everything on `Machine` works with any cell type. Circuits, pipelines and
+// networks are built from `isize` machines only.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use num_bigint::BigInt;
+
+pub trait Cell:
+    Clone + Default + Eq + Ord + fmt::Debug + fmt::Display + FromStr + Send + 'static
+{
+    fn from_isize(value: isize) -> Self;
+
+    /// The value as an isize, if it fits
+    fn to_isize(&self) -> Option<isize>;
+
+    /// The sum, wrapped around if it overflowed, and whether it did
+    fn overflowing_add(&self, other: &Self) -> (Self, bool);
+
+    /// The product, wrapped around if it overflowed, and whether it did
+    fn overflowing_mul(&self, other: &Self) -> (Self, bool);
+
+    fn saturating_add(&self, other: &Self) -> Self;
+
+    fn saturating_mul(&self, other: &Self) -> Self;
+}
+
+macro_rules! primitive_cell {
+    ($t:ty) => {
+        impl Cell for $t {
+            fn from_isize(value: isize) -> Self {
+                value as $t
+            }
+
+            fn to_isize(&self) -> Option<isize> {
+                isize::try_from(*self).ok()
+            }
+
+            fn overflowing_add(&self, other: &Self) -> (Self, bool) {
+                <$t>::overflowing_add(*self, *other)
+            }
+
+            fn overflowing_mul(&self, other: &Self) -> (Self, bool) {
+                <$t>::overflowing_mul(*self, *other)
+            }
+
+            fn saturating_add(&self, other: &Self) -> Self {
+                <$t>::saturating_add(*self, *other)
+            }
+
+            fn saturating_mul(&self, other: &Self) -> Self {
+                <$t>::saturating_mul(*self, *other)
+            }
+        }
+    };
+}
+
+primitive_cell!(isize);
+primitive_cell!(i128);
+
+// Big integers never overflow
+impl Cell for BigInt {
+    fn from_isize(value: isize) -> Self {
+        BigInt::from(value)
+    }
+
+    fn to_isize(&self) -> Option<isize> {
+        isize::try_from(self).ok()
+    }
+
+    fn overflowing_add(&self, other: &Self) -> (Self, bool) {
+        (self + other, false)
+    }
+
+    fn overflowing_mul(&self, other: &Self) -> (Self, bool) {
+        (self * other, false)
+    }
+
+    fn saturating_add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn saturating_mul(&self, other: &Self) -> Self {
+        self * other
+    }
+}
+
+// Squares `x` `n` times and outputs the result
+#[cfg(test)]
+fn square<C: Cell>(x: C, n: isize) -> crate::Machine<C> {
+    let program = crate::asm::assemble(
+        "
+        loop:   MUL x, x, x
+                ADD n, #-1, n
+                JIT n, #loop
+                OUT x
+                HALT
+        x:      DATA 0
+        n:      DATA 0
+        ",
+    )
+    .unwrap();
+    let len = program.len();
+
+    let mut cells: Vec<C> = program.into_iter().map(C::from_isize).collect();
+    cells[len - 2] = x;
+    cells[len - 1] = C::from_isize(n);
+
+    let mut m = crate::Machine::<C>::new();
+    m.init(&cells);
+    m
+}
+
+#[test]
+fn test_isize_overflow() {
+    let mut m = square(1_000_000_000_000_isize, 1);
+
+    assert_eq!(
+        m.run(),
+        Ok(Some(
+            1_000_000_000_000_isize.wrapping_mul(1_000_000_000_000)
+        ))
+    );
+    assert!(m.register().carry_flag_set());
+}
+
+#[test]
+fn test_i128() {
+    let mut m = square(1_000_000_000_000_i128, 1);
+
+    assert_eq!(m.run(), Ok(Some(1_000_000_000_000_000_000_000_000)));
+    assert!(!m.register().carry_flag_set());
+}
+
+#[test]
+fn test_big_integer() {
+    let x: BigInt = "1000000000000".parse().unwrap();
+    let mut m = square(x, 3);
+
+    let expected: BigInt = format!("1{}", "0".repeat(96)).parse().unwrap();
+    assert_eq!(m.run(), Ok(Some(expected)));
+    assert!(!m.register().carry_flag_set());
+}
+
+#[test]
+fn test_big_integer_io() {
+    let big: BigInt = format!("-{}", "9".repeat(40)).parse().unwrap();
+
+    let mut m = crate::Machine::<BigInt>::new();
+    m.init(
+        &[3, 0, 4, 0, 99]
+            .iter()
+            .map(|v| BigInt::from(*v))
+            .collect::<Vec<_>>(),
+    );
+    m.input(big.clone());
+
+    assert_eq!(m.run(), Ok(Some(big)));
+}
+
+#[test]
+fn test_big_address() {
+    let address: BigInt = "1000000000000000000000000000000".parse().unwrap();
+
+    let mut m = crate::Machine::<BigInt>::new();
+    m.init(&[BigInt::from(4), address, BigInt::from(99)]);
+
+    assert_eq!(
+        m.run(),
+        Err(crate::IntcodeError::AddressOutOfRange {
+            instruction_pointer: 0,
+            code: 4,
+            address: "1000000000000000000000000000000".to_string(),
+        })
+    );
+}
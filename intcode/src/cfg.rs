@@ -0,0 +1,311 @@
+// Control flow is recovered statically by following every path from address
+// 0 through the decoded program. JIT and JIF with an immediate target jump
+// to a known address, but a target read from memory is only known at run
+// time, so those jumps are marked as indirect. So are custom op codes, which
+// may jump anywhere. The analysis takes the program as written; code that
+// modifies itself may behave differently.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Write;
+
+use crate::disasm::{self, Line};
+use crate::{InstructionSet, Machine, Mode, Operation};
+
+/// Where control goes after a block
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// On to the next instruction
+    Fallthrough(usize),
+    /// A jump to a known address
+    Jump(usize),
+    /// A jump to an address read from memory
+    Indirect,
+    Halt,
+    /// Into a cell that is not a valid instruction, or to a negative address
+    Invalid,
+}
+
+/// A run of instructions that is only ever entered at its first
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    /// One past the last cell of the last instruction
+    pub end: usize,
+    pub lines: Vec<Line>,
+    pub exits: Vec<Exit>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Graph {
+    /// Blocks by start address
+    pub blocks: BTreeMap<usize, Block>,
+}
+
+// Where control can go after the instruction at `address`, and its length
+fn successors(
+    instruction_set: &InstructionSet,
+    program: &[isize],
+    address: usize,
+) -> Option<(usize, Vec<Exit>)> {
+    let (operation, params) = disasm::decode(instruction_set, program, address)?;
+    let next = address + params.len() + 1;
+
+    let jump_if = match operation {
+        Operation::HALT => return Some((next - address, vec![Exit::Halt])),
+        Operation::Custom(_) => {
+            return Some((
+                next - address,
+                vec![Exit::Indirect, Exit::Fallthrough(next)],
+            ))
+        }
+        Operation::JIT => true,
+        Operation::JIF => false,
+        _ => return Some((next - address, vec![Exit::Fallthrough(next)])),
+    };
+
+    // An immediate condition decides the branch before the program runs
+    let (may_jump, may_fall) = match params[0] {
+        (Mode::Immediate, value) => ((value != 0) == jump_if, (value != 0) != jump_if),
+        _ => (true, true),
+    };
+
+    let mut exits = Vec::new();
+    if may_jump {
+        exits.push(match params[1] {
+            (Mode::Immediate, target) if target >= 0 => Exit::Jump(target as usize),
+            (Mode::Immediate, _) => Exit::Invalid,
+            _ => Exit::Indirect,
+        });
+    }
+    if may_fall {
+        exits.push(Exit::Fallthrough(next));
+    }
+
+    Some((next - address, exits))
+}
+
+/// Recovers the basic blocks reachable from address 0 and the edges between
+/// them
+pub fn control_flow(program: &[isize]) -> Graph {
+    recover(&InstructionSet::default(), program)
+}
+
+fn recover(instruction_set: &InstructionSet, program: &[isize]) -> Graph {
+    // Every block starts at address 0, at a jump target or after a branch
+    let mut leaders = BTreeSet::new();
+    let mut seen = HashSet::new();
+    let mut work = vec![0];
+    leaders.insert(0);
+
+    while let Some(address) = work.pop() {
+        if !seen.insert(address) {
+            continue;
+        }
+        if let Some((_, exits)) = successors(instruction_set, program, address) {
+            let branches = exits.iter().any(|e| !matches!(e, Exit::Fallthrough(_)));
+            for exit in exits {
+                match exit {
+                    Exit::Jump(target) => {
+                        leaders.insert(target);
+                        work.push(target);
+                    }
+                    Exit::Fallthrough(next) => {
+                        if branches {
+                            leaders.insert(next);
+                        }
+                        work.push(next);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let mut graph = Graph::default();
+
+    for &start in &leaders {
+        let mut block = Block {
+            start,
+            end: start,
+            lines: Vec::new(),
+            exits: Vec::new(),
+        };
+
+        loop {
+            let (len, exits) = match successors(instruction_set, program, block.end) {
+                Some(successors) => successors,
+                None => {
+                    block.exits.push(Exit::Invalid);
+                    break;
+                }
+            };
+
+            block
+                .lines
+                .push(disasm::decode_line(instruction_set, program, block.end));
+            block.end += len;
+
+            if exits != [Exit::Fallthrough(block.end)] || leaders.contains(&block.end) {
+                block.exits = exits;
+                break;
+            }
+        }
+
+        graph.blocks.insert(start, block);
+    }
+
+    graph
+}
+
+impl Machine {
+    /// Recovers the control flow of the program in memory, with custom op
+    /// codes decoded by name
+    pub fn control_flow(&self) -> Graph {
+        let program: Vec<isize> = (0..self.memory.len())
+            .map(|loc| self.memory.get(loc))
+            .collect();
+        recover(&self.instruction_set, &program)
+    }
+}
+
+impl Graph {
+    /// Every edge between blocks, as (from, to) start addresses
+    pub fn edges(&self) -> Vec<(usize, usize)> {
+        self.blocks
+            .values()
+            .flat_map(|block| {
+                block.exits.iter().filter_map(move |exit| match exit {
+                    Exit::Fallthrough(to) | Exit::Jump(to) => Some((block.start, *to)),
+                    _ => None,
+                })
+            })
+            .collect()
+    }
+
+    /// The graph in Graphviz DOT format. Jumps are labelled; indirect jumps
+    /// lead to a `?` node and invalid exits to a `!` node.
+    pub fn dot(&self) -> String {
+        let mut s = String::new();
+
+        writeln!(s, "digraph intcode {{").unwrap();
+        writeln!(s, "    node [shape=box, fontname=monospace];").unwrap();
+
+        for block in self.blocks.values() {
+            let label: String = block
+                .lines
+                .iter()
+                .map(|line| format!("{}: {}\\l", line.address, line.text))
+                .collect();
+            writeln!(s, "    b{} [label=\"{}\"];", block.start, label).unwrap();
+
+            for exit in &block.exits {
+                match exit {
+                    Exit::Fallthrough(to) => writeln!(s, "    b{} -> b{};", block.start, to),
+                    Exit::Jump(to) => {
+                        writeln!(s, "    b{} -> b{} [label=jump];", block.start, to)
+                    }
+                    Exit::Indirect => writeln!(
+                        s,
+                        "    i{0} [label=\"?\", shape=circle];\n    b{0} -> i{0} [label=jump, style=dashed];",
+                        block.start
+                    ),
+                    Exit::Invalid => writeln!(
+                        s,
+                        "    x{0} [label=\"!\", shape=circle];\n    b{0} -> x{0} [style=dashed];",
+                        block.start
+                    ),
+                    Exit::Halt => Ok(()),
+                }
+                .unwrap();
+            }
+        }
+
+        writeln!(s, "}}").unwrap();
+        s
+    }
+}
+
+#[test]
+fn test_control_flow() {
+    // Outputs 2 or 1 depending on whether the input is true, then the input
+    let program = crate::asm::assemble(
+        "
+                IN x
+                JIT x, #then
+                OUT #1
+                JIT #1, #join
+        then:   OUT #2
+        join:   OUT x
+                HALT
+        x:      DATA 0
+        ",
+    )
+    .unwrap();
+
+    let graph = control_flow(&program);
+
+    let blocks: Vec<(usize, usize, Vec<Exit>)> = graph
+        .blocks
+        .values()
+        .map(|b| (b.start, b.end, b.exits.clone()))
+        .collect();
+    assert_eq!(
+        blocks,
+        vec![
+            (0, 5, vec![Exit::Jump(10), Exit::Fallthrough(5)]),
+            (5, 10, vec![Exit::Jump(12)]),
+            (10, 12, vec![Exit::Fallthrough(12)]),
+            (12, 15, vec![Exit::Halt]),
+        ]
+    );
+    assert_eq!(graph.blocks[&0].lines[1].text, "JIT 15, #10");
+    assert_eq!(graph.edges(), vec![(0, 10), (0, 5), (5, 12), (10, 12)]);
+
+    assert_eq!(
+        graph.dot(),
+        "digraph intcode {\n    \
+         node [shape=box, fontname=monospace];\n    \
+         b0 [label=\"0: IN 15\\l2: JIT 15, #10\\l\"];\n    \
+         b0 -> b10 [label=jump];\n    \
+         b0 -> b5;\n    \
+         b5 [label=\"5: OUT #1\\l7: JIT #1, #12\\l\"];\n    \
+         b5 -> b12 [label=jump];\n    \
+         b10 [label=\"10: OUT #2\\l\"];\n    \
+         b10 -> b12;\n    \
+         b12 [label=\"12: OUT 15\\l14: HALT\\l\"];\n\
+         }\n"
+    );
+}
+
+#[test]
+fn test_control_flow_branches() {
+    // Unconditional immediate jump, a never taken jump, an indirect jump and
+    // a jump into data
+    let program = [
+        1105, 1, 6, 99, 99, 99, // 0: JIT #1, #6
+        1106, 1, 3, // 6: JIF #1, #3
+        1005, 20, 15, // 9: JIT 20, #15
+        5, 20, 21, // 12: JIT 20, 21
+        1105, 1, 19, 99, 42, // 15: JIT #1, #19
+    ];
+
+    let graph = control_flow(&program);
+
+    let exits: Vec<(usize, Vec<Exit>)> = graph
+        .blocks
+        .values()
+        .map(|b| (b.start, b.exits.clone()))
+        .collect();
+    assert_eq!(
+        exits,
+        vec![
+            (0, vec![Exit::Jump(6)]),
+            // The never taken jump does not end the block
+            (6, vec![Exit::Jump(15), Exit::Fallthrough(12)]),
+            (12, vec![Exit::Indirect, Exit::Fallthrough(15)]),
+            (15, vec![Exit::Jump(19)]),
+            (19, vec![Exit::Invalid]),
+        ]
+    );
+    assert!(graph.blocks[&19].lines.is_empty());
+}
@@ -0,0 +1,335 @@
+use crate::{IntcodeError, Machine, Status};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// `isize` machines that each run on their own thread, with the outputs of one
+/// machine connected to the inputs of others through channels. A machine
+/// reads its queued input first and then blocks until an upstream machine
+/// produces a value.
+#[derive(Default)]
+pub struct Circuit {
+    machines: Vec<Machine>,
+    links: Vec<(usize, usize)>,
+}
+
+// What every machine is doing, shared by all threads so that a circuit in
+// which every running machine waits on an empty channel is detected instead
+// of blocking forever. A woken machine still counts as waiting until it
+// runs, so values sent but not yet received are counted as well.
+struct State {
+    running: Vec<bool>,
+    waiting: Vec<bool>,
+    pending: Vec<usize>,
+    deadlocked: bool,
+}
+
+struct Monitor {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl State {
+    fn all_waiting(&self) -> bool {
+        (0..self.running.len())
+            .all(|i| !self.running[i] || (self.waiting[i] && self.pending[i] == 0))
+    }
+}
+
+impl Monitor {
+    fn new(machines: usize) -> Monitor {
+        Monitor {
+            state: Mutex::new(State {
+                running: vec![true; machines],
+                waiting: vec![false; machines],
+                pending: vec![0; machines],
+                deadlocked: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    // Sends under the lock, so a machine never sees its channel empty while
+    // a value for it is on the way
+    fn send(&self, to: usize, sender: &Sender<isize>, value: isize) {
+        let mut state = self.state.lock().unwrap();
+        if sender.send(value).is_ok() {
+            state.pending[to] += 1;
+        }
+        self.changed.notify_all();
+    }
+
+    // Blocks until machine `index` has a value, or gives None once no more
+    // can arrive
+    fn receive(&self, index: usize, receiver: &Receiver<isize>) -> Option<isize> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match receiver.try_recv() {
+                Ok(value) => {
+                    state.pending[index] -= 1;
+                    return Some(value);
+                }
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => {}
+            }
+
+            state.waiting[index] = true;
+            if state.deadlocked || state.all_waiting() {
+                state.deadlocked = true;
+                self.changed.notify_all();
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+            state.waiting[index] = false;
+        }
+    }
+
+    fn finished(&self, index: usize) {
+        self.state.lock().unwrap().running[index] = false;
+        self.changed.notify_all();
+    }
+}
+
+fn run_machine(
+    m: &mut Machine,
+    senders: &[(usize, Sender<isize>)],
+    monitor: &Monitor,
+) -> Result<Vec<isize>, IntcodeError> {
+    let mut produced = Vec::new();
+    loop {
+        match m.resume()? {
+            Status::Output(output) => {
+                for (to, sender) in senders {
+                    monitor.send(*to, sender, output);
+                }
+                produced.push(output);
+            }
+            Status::NeedInput => {
+                let ip = m.register().instruction_pointer();
+                return Err(IntcodeError::NoInput {
+                    instruction_pointer: ip,
+                    code: m.memory.get(ip),
+                });
+            }
+            Status::Halted => return Ok(produced),
+            _ => {}
+        }
+    }
+}
+
+impl Circuit {
+    pub fn new() -> Circuit {
+        Circuit::default()
+    }
+
+    /// Adds a machine and returns its index for `connect`
+    pub fn add(&mut self, machine: Machine) -> usize {
+        self.machines.push(machine);
+        self.machines.len() - 1
+    }
+
+    /// Sends every output of machine `from` to the input of machine `to`.
+    /// Panics if either machine has not been added.
+    pub fn connect(&mut self, from: usize, to: usize) {
+        for index in &[from, to] {
+            assert!(
+                *index < self.machines.len(),
+                "Cannot connect machine {}: the circuit has {} machines",
+                index,
+                self.machines.len()
+            );
+        }
+        self.links.push((from, to));
+    }
+
+    /// Runs every machine until it halts and returns each machine's outputs.
+    ///
+    /// A machine whose upstream machines have all halted gets no more input,
+    /// so it fails with `IntcodeError::NoInput` if it asks for more. So does
+    /// every machine once all running machines are waiting for input. Once
+    /// all threads are done, the error of the first machine that failed for
+    /// any other reason is returned, or else the first `NoInput`.
+    pub fn run(self) -> Result<Vec<Vec<isize>>, IntcodeError> {
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            self.machines.iter().map(|_| mpsc::channel()).unzip();
+
+        let mut outputs: Vec<Vec<(usize, Sender<isize>)>> =
+            self.machines.iter().map(|_| vec![]).collect();
+        for (from, to) in &self.links {
+            outputs[*from].push((*to, senders[*to].clone()));
+        }
+
+        // Only the connected machines hold senders, so a channel closes once
+        // every machine feeding it has finished
+        drop(senders);
+
+        let monitor = Arc::new(Monitor::new(self.machines.len()));
+
+        let handles: Vec<_> = self
+            .machines
+            .into_iter()
+            .zip(receivers)
+            .zip(outputs)
+            .enumerate()
+            .map(|(index, ((mut m, receiver), senders))| {
+                let monitor = Arc::clone(&monitor);
+                thread::spawn(move || {
+                    let source = Arc::clone(&monitor);
+                    m.set_input_source(move || source.receive(index, &receiver));
+
+                    let result = run_machine(&mut m, &senders, &monitor);
+
+                    // Closes the channels this machine feeds before anyone
+                    // waiting on them is woken
+                    drop(senders);
+                    drop(m);
+                    monitor.finished(index);
+
+                    result
+                })
+            })
+            .collect();
+
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Machine thread panicked"))
+            .collect();
+
+        // A machine starved of input is usually a symptom of another one
+        // failing, so any other error is reported ahead of it
+        let cause = results
+            .iter()
+            .filter_map(|result| result.as_ref().err())
+            .find(|e| !matches!(e, IntcodeError::NoInput { .. }));
+        match cause {
+            Some(e) => Err(e.clone()),
+            None => results.into_iter().collect(),
+        }
+    }
+}
+
+#[test]
+fn test_circuit_chain() {
+    let mut circuit = Circuit::new();
+
+    let mut doubler = Machine::default();
+    doubler.init(&[3, 9, 102, 2, 9, 9, 4, 9, 99, 0]);
+    doubler.input(21);
+
+    let mut incrementer = Machine::default();
+    incrementer.init(&[3, 9, 101, 1, 9, 9, 4, 9, 99, 0]);
+
+    let a = circuit.add(doubler);
+    let b = circuit.add(incrementer);
+    circuit.connect(a, b);
+
+    assert_eq!(circuit.run(), Ok(vec![vec![42], vec![43]]));
+}
+
+#[test]
+fn test_circuit_feedback_loop() {
+    let p = crate::fixtures::LONG_FEEDBACK_LOOP;
+    let seq = [9, 7, 8, 5, 6];
+
+    let mut circuit = Circuit::new();
+    for (i, phase) in seq.iter().enumerate() {
+        let mut m = Machine::default();
+        m.init(&p);
+        m.input(*phase);
+        if i == 0 {
+            m.input(0);
+        }
+        circuit.add(m);
+    }
+    for i in 0..5 {
+        circuit.connect(i, (i + 1) % 5);
+    }
+
+    let outputs = circuit.run().unwrap();
+
+    assert_eq!(outputs[4].last(), Some(&18_216));
+}
+
+#[test]
+fn test_circuit_deadlock() {
+    let mut circuit = Circuit::new();
+    for _ in 0..2 {
+        let mut m = Machine::default();
+        m.init(&[3, 0, 99]);
+        circuit.add(m);
+    }
+    circuit.connect(0, 1);
+    circuit.connect(1, 0);
+
+    assert_eq!(
+        circuit.run(),
+        Err(IntcodeError::NoInput {
+            instruction_pointer: 0,
+            code: 3
+        })
+    );
+
+    // One machine halts while the other two wait on each other
+    let mut circuit = Circuit::new();
+    for p in &[[3, 0, 99], [3, 0, 99], [104, 1, 99]] {
+        let mut m = Machine::default();
+        m.init(p);
+        circuit.add(m);
+    }
+    circuit.connect(0, 1);
+    circuit.connect(1, 0);
+
+    assert_eq!(
+        circuit.run(),
+        Err(IntcodeError::NoInput {
+            instruction_pointer: 0,
+            code: 3
+        })
+    );
+}
+
+#[test]
+fn test_circuit_error_cause() {
+    // Machine 1 fails before sending anything, starving machine 0
+    let mut circuit = Circuit::new();
+    for p in &[&[3, 0, 99][..], &[42]] {
+        let mut m = Machine::default();
+        m.init(p);
+        circuit.add(m);
+    }
+    circuit.connect(1, 0);
+
+    assert_eq!(
+        circuit.run(),
+        Err(IntcodeError::UnknownInstruction {
+            instruction_pointer: 0,
+            code: 42
+        })
+    );
+}
+
+#[test]
+#[should_panic(expected = "Cannot connect machine 2: the circuit has 2 machines")]
+fn test_circuit_connect_unknown_machine() {
+    let mut circuit = Circuit::new();
+    circuit.add(Machine::default());
+    circuit.add(Machine::default());
+    circuit.connect(0, 2);
+}
+
+#[test]
+fn test_circuit_starved() {
+    let mut circuit = Circuit::new();
+
+    let mut m = Machine::default();
+    m.init(&[3, 0, 99]);
+    circuit.add(m);
+
+    assert_eq!(
+        circuit.run(),
+        Err(IntcodeError::NoInput {
+            instruction_pointer: 0,
+            code: 3
+        })
+    );
+}
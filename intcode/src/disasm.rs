@@ -0,0 +1,235 @@
+use crate::{Cell, InstructionSet, Machine, Memory, Mode, Operation};
+use std::fmt;
+
+/// A decoded instruction, or a single cell of data that does not decode
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line<C = isize> {
+    pub address: usize,
+    pub raw: Vec<C>,
+    pub text: String,
+}
+
+impl<C: Cell> fmt::Display for Line<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw: Vec<String> = self.raw.iter().map(|v| v.to_string()).collect();
+        write!(
+            f,
+            "{:>5}: {:<24} ; {}",
+            self.address,
+            self.text,
+            raw.join(",")
+        )
+    }
+}
+
+fn param<C: Cell>(mode: Mode, val: C) -> String {
+    match mode {
+        Mode::Position => val.to_string(),
+        Mode::Immediate => format!("#{}", val),
+        Mode::Relative => format!("@{}", val),
+    }
+}
+
+// Cells decode as an instruction only if the op code is known, every
+// parameter mode is valid, there are no mode digits beyond the last parameter
+// and all parameters fit in the program. Anything else is listed as data so
+// that the listing always reassembles to the same program.
+pub(crate) fn decode<C: Cell>(
+    instruction_set: &InstructionSet<C>,
+    program: &[C],
+    address: usize,
+) -> Option<(Operation, Vec<(Mode, C)>)> {
+    let code = program.get(address)?.to_isize()?;
+    let (operation, arg_len) = instruction_set.decode(code)?;
+
+    let end = address + arg_len + 1;
+    if end > program.len() || code / 100 / 10_isize.pow(arg_len as u32) != 0 {
+        return None;
+    }
+
+    let params = (0..arg_len)
+        .map(|i| Some((Mode::from_code(code, i)?, program[address + i + 1].clone())))
+        .collect::<Option<Vec<_>>>()?;
+
+    Some((operation, params))
+}
+
+pub(crate) fn decode_line<C: Cell>(
+    instruction_set: &InstructionSet<C>,
+    program: &[C],
+    address: usize,
+) -> Line<C> {
+    let code = program[address].clone();
+
+    let instruction = decode(instruction_set, program, address).map(|(operation, params)| {
+        let end = address + params.len() + 1;
+        let params: Vec<String> = params
+            .into_iter()
+            .map(|(mode, val)| param(mode, val))
+            .collect();
+
+        Line {
+            address,
+            raw: program[address..end].to_vec(),
+            text: format!("{} {}", operation, params.join(", "))
+                .trim_end()
+                .to_string(),
+        }
+    });
+
+    instruction.unwrap_or_else(|| Line {
+        address,
+        text: format!("DATA {}", code),
+        raw: vec![code],
+    })
+}
+
+/// Decodes the single instruction starting at `address`
+pub fn disassemble_at(program: &[isize], address: usize) -> Line {
+    decode_line(&InstructionSet::default(), program, address)
+}
+
+/// Decodes a whole program, starting from address 0
+pub fn disassemble(program: &[isize]) -> Vec<Line> {
+    let instruction_set = InstructionSet::default();
+
+    let mut lines = Vec::new();
+    let mut address = 0;
+
+    while address < program.len() {
+        let line = decode_line(&instruction_set, program, address);
+        address += line.raw.len();
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Decodes `count` instructions of a machine's memory starting at `address`,
+/// with the standard instruction set
+pub fn disassemble_memory<C: Cell>(
+    memory: &Memory<C>,
+    address: usize,
+    count: usize,
+) -> Vec<Line<C>> {
+    disassemble_window(&InstructionSet::default(), memory, address, count)
+}
+
+fn disassemble_window<C: Cell>(
+    instruction_set: &InstructionSet<C>,
+    memory: &Memory<C>,
+    address: usize,
+    count: usize,
+) -> Vec<Line<C>> {
+    // No instruction is longer than four cells. The window stops at the end
+    // of the address space.
+    let window: Vec<C> = (address..=usize::MAX)
+        .take(count.saturating_mul(4))
+        .map(|loc| memory.get(loc))
+        .collect();
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while lines.len() < count && offset < window.len() {
+        let mut line = decode_line(instruction_set, &window, offset);
+        offset += line.raw.len();
+        line.address += address;
+        lines.push(line);
+    }
+
+    lines
+}
+
+impl<C: Cell> Machine<C> {
+    /// Decodes `count` instructions of memory starting at `address`. Custom
+    /// op codes are listed by name.
+    pub fn disassemble(&self, address: usize, count: usize) -> Vec<Line<C>> {
+        disassemble_window(&self.instruction_set, &self.memory, address, count)
+    }
+}
+
+/// Formats a whole program as an annotated listing, one line per instruction
+pub fn listing(program: &[isize]) -> String {
+    disassemble(program)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[test]
+fn test_disassemble() {
+    let lines = disassemble(&[1002, 4, 3, 4, 33, 109, -1, 204, 1, 99]);
+
+    let text: Vec<&str> = lines.iter().map(|l| l.text.as_str()).collect();
+    assert_eq!(
+        text,
+        vec!["MUL 4, #3, 4", "DATA 33", "ARB #-1", "OUT @1", "HALT"]
+    );
+
+    let addresses: Vec<usize> = lines.iter().map(|l| l.address).collect();
+    assert_eq!(addresses, vec![0, 4, 5, 7, 9]);
+}
+
+#[test]
+fn test_disassemble_data_fallback() {
+    // Unknown op code, invalid mode, mode on a missing parameter, truncated
+    let lines = disassemble(&[42, 304, 0, 1104, 0, 0, 1]);
+
+    let text: Vec<&str> = lines.iter().map(|l| l.text.as_str()).collect();
+    assert_eq!(
+        text,
+        vec![
+            "DATA 42",
+            "DATA 304",
+            "DATA 0",
+            "DATA 1104",
+            "DATA 0",
+            "DATA 0",
+            "DATA 1"
+        ]
+    );
+}
+
+#[test]
+fn test_listing() {
+    assert_eq!(
+        listing(&[3, 9, 8, 9, 10, 9, 4, 9, 99]),
+        "    0: IN 9                     ; 3,9\n\
+         \x20   2: EQ 9, 10, 9              ; 8,9,10,9\n\
+         \x20   6: OUT 9                    ; 4,9\n\
+         \x20   8: HALT                     ; 99\n"
+    );
+}
+
+#[test]
+fn test_disassemble_memory() {
+    let mut m = crate::Machine::default();
+    m.init(&[1002, 4, 3, 4, 33, 109, -1, 204, 1, 99]);
+
+    let lines = disassemble_memory(&m.memory, 4, 3);
+
+    let text: Vec<&str> = lines.iter().map(|l| l.text.as_str()).collect();
+    assert_eq!(text, vec!["DATA 33", "ARB #-1", "OUT @1"]);
+    assert_eq!(lines[2].address, 7);
+}
+
+#[test]
+fn test_disassemble_big_integers() {
+    let mut m = crate::Machine::<crate::BigInt>::new();
+    m.init(&[
+        "1101".parse().unwrap(),
+        "123456789012345678901234567890".parse().unwrap(),
+        0.into(),
+        7.into(),
+        "99999999999999999999".parse().unwrap(),
+    ]);
+
+    let lines = disassemble_memory(&m.memory, 0, 2);
+    assert_eq!(
+        lines[0].to_string(),
+        "    0: ADD #123456789012345678901234567890, #0, 7 ; \
+         1101,123456789012345678901234567890,0,7"
+    );
+    assert_eq!(lines[1].text, "DATA 99999999999999999999");
+}
@@ -0,0 +1,217 @@
+use crate::{Cell, Machine};
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// Where IN instructions read from once the machine's own input queue is empty
+pub trait InputSource<C = isize> {
+    /// The next input value, or None if there is none to give. A machine that
+    /// gets None stops with `Status::NeedInput`.
+    fn next_input(&mut self) -> Option<C>;
+}
+
+/// Where OUT instructions write to
+pub trait OutputSink<C = isize> {
+    fn write_output(&mut self, value: C);
+}
+
+impl<C, F: FnMut() -> Option<C>> InputSource<C> for F {
+    fn next_input(&mut self) -> Option<C> {
+        self()
+    }
+}
+
+impl<C, F: FnMut(C)> OutputSink<C> for F {
+    fn write_output(&mut self, value: C) {
+        self(value)
+    }
+}
+
+impl<C> InputSource<C> for VecDeque<C> {
+    fn next_input(&mut self) -> Option<C> {
+        self.pop_front()
+    }
+}
+
+impl<C> OutputSink<C> for VecDeque<C> {
+    fn write_output(&mut self, value: C) {
+        self.push_back(value)
+    }
+}
+
+impl<C> OutputSink<C> for Vec<C> {
+    fn write_output(&mut self, value: C) {
+        self.push(value)
+    }
+}
+
+/// Blocks until a value is sent; gives no input once every sender is gone
+impl<C> InputSource<C> for Receiver<C> {
+    fn next_input(&mut self) -> Option<C> {
+        self.recv().ok()
+    }
+}
+
+/// Values written after the receiver is gone are dropped
+impl<C> OutputSink<C> for Sender<C> {
+    fn write_output(&mut self, value: C) {
+        self.send(value).ok();
+    }
+}
+
+/// Queues every output as input of another machine
+impl<C: Cell> OutputSink<C> for Arc<Mutex<Machine<C>>> {
+    fn write_output(&mut self, value: C) {
+        if let Ok(mut m) = self.lock() {
+            m.input(value)
+        }
+    }
+}
+
+/// Reads input from an iterator
+pub struct IterSource<I>(pub I);
+
+impl<C, I: Iterator<Item = C>> InputSource<C> for IterSource<I> {
+    fn next_input(&mut self) -> Option<C> {
+        self.0.next()
+    }
+}
+
+/// Reads one integer per line, for example from a file. Blank lines are
+/// skipped; a line that is not an integer ends the input.
+pub struct LineSource<R>(pub R);
+
+impl<C: Cell, R: BufRead> InputSource<C> for LineSource<R> {
+    fn next_input(&mut self) -> Option<C> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.0.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            if !line.trim().is_empty() {
+                return line.trim().parse().ok();
+            }
+        }
+    }
+}
+
+/// Writes one integer per line, for example to a file
+pub struct LineSink<W>(pub W);
+
+impl<C: Cell, W: Write> OutputSink<C> for LineSink<W> {
+    fn write_output(&mut self, value: C) {
+        writeln!(self.0, "{}", value).ok();
+    }
+}
+
+/// The source and sink a machine is connected to, if any
+pub(crate) struct Io<C = isize> {
+    source: Option<Box<dyn InputSource<C> + Send>>,
+    sink: Option<Box<dyn OutputSink<C> + Send>>,
+}
+
+// Neither box holds a `C`, so unlike a derived impl this needs no `C: Default`
+impl<C> Default for Io<C> {
+    fn default() -> Io<C> {
+        Io {
+            source: None,
+            sink: None,
+        }
+    }
+}
+
+impl<C> Io<C> {
+    pub(crate) fn set_source(&mut self, source: Option<Box<dyn InputSource<C> + Send>>) {
+        self.source = source;
+    }
+
+    pub(crate) fn set_sink(&mut self, sink: Option<Box<dyn OutputSink<C> + Send>>) {
+        self.sink = sink;
+    }
+
+    pub(crate) fn has_sink(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    pub(crate) fn read(&mut self) -> Option<C> {
+        self.source.as_mut().and_then(|source| source.next_input())
+    }
+
+    pub(crate) fn write(&mut self, value: C) {
+        if let Some(sink) = self.sink.as_mut() {
+            sink.write_output(value)
+        }
+    }
+}
+
+#[test]
+fn test_closures() {
+    let mut m = Machine::default();
+    m.init(&[3, 0, 4, 0, 3, 0, 4, 0, 99]);
+
+    let mut next = 0;
+    m.set_input_source(move || {
+        next += 10;
+        Some(next)
+    });
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let sink = seen.clone();
+    m.set_output_sink(move |value| sink.lock().unwrap().push(value));
+
+    assert_eq!(m.run(), Ok(Some(20)));
+    assert_eq!(*seen.lock().unwrap(), vec![10, 20]);
+
+    // Outputs went to the sink, not the machine's own buffer
+    assert_eq!(m.drain_output(), vec![]);
+}
+
+#[test]
+fn test_queue_before_source() {
+    let mut m = Machine::default();
+    m.init(&[3, 0, 4, 0, 3, 0, 4, 0, 3, 0, 4, 0, 99]);
+
+    m.input(1);
+    m.set_input_source(IterSource(vec![2].into_iter()));
+
+    assert_eq!(m.resume(), Ok(crate::Status::Output(1)));
+    assert_eq!(m.resume(), Ok(crate::Status::Output(2)));
+    assert_eq!(m.resume(), Ok(crate::Status::NeedInput));
+    m.input(3);
+    assert_eq!(m.resume(), Ok(crate::Status::Output(3)));
+}
+
+#[test]
+fn test_lines() {
+    let mut m = Machine::default();
+    m.init(&[3, 0, 3, 1, 1, 0, 1, 0, 4, 0, 99]);
+
+    let path = std::env::temp_dir().join("intcode_test_lines.txt");
+
+    m.set_input_source(LineSource(std::io::Cursor::new("1\n\n41\n")));
+    m.set_output_sink(LineSink(std::fs::File::create(&path).unwrap()));
+    m.run().unwrap();
+    m.clear_io();
+
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "42\n");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_machine_to_machine() {
+    let downstream = Arc::new(Mutex::new(Machine::default()));
+    downstream
+        .lock()
+        .unwrap()
+        .init(&[3, 0, 3, 1, 1, 0, 1, 0, 4, 0, 99]);
+
+    let mut upstream = Machine::default();
+    upstream.init(&[104, 40, 104, 2, 99]);
+    upstream.set_output_sink(downstream.clone());
+    upstream.run().unwrap();
+
+    assert_eq!(downstream.lock().unwrap().run(), Ok(Some(42)));
+}
@@ -1,9 +1,52 @@
-use bit_vec::BitVec;
-use std::collections::{HashMap, VecDeque};
-use std::fs;
-
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
-enum Operation {
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use crate::builder::Context;
+use crate::cache::DecodeCache;
+use crate::io::{InputSource, Io, OutputSink};
+use crate::profile::Profile;
+use crate::selfmod::CodeWrites;
+
+pub use crate::builder::{Handler, MachineBuilder};
+pub use crate::cell::Cell;
+pub use num_bigint::BigInt;
+
+pub mod ascii;
+pub mod asm;
+pub mod builder;
+pub mod cache;
+pub mod cell;
+pub mod cfg;
+pub mod circuit;
+pub mod disasm;
+#[cfg(test)]
+mod fixtures;
+pub mod io;
+pub mod network;
+pub mod pipeline;
+pub mod profile;
+pub mod selfmod;
+pub mod snapshot;
+pub mod trace;
+
+const PAGE_SIZE: usize = 1024;
+
+// Op codes are the low two digits of an instruction, so the dispatch table
+// has one slot for each of them
+const OP_CODES: usize = 100;
+
+const MAX_ARGS: usize = 3;
+
+// Divisor extracting the mode digit of each parameter from an instruction
+const MODE_DIVISORS: [isize; MAX_ARGS] = [100, 1_000, 10_000];
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
     ADD,
     MUL,
     IN,
@@ -12,54 +55,444 @@ enum Operation {
     JIF,
     LT,
     EQ,
+    ARB,
     HALT,
+    // An op code added with `MachineBuilder`, by its mnemonic
+    Custom(&'static str),
 }
 
-struct Instruction {
-    operation: Operation,
-    args: Vec<usize>,
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A decoded instruction with its arguments resolved to memory addresses
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub operation: Operation,
+    op_code: usize,
+    args: [usize; MAX_ARGS],
+    arg_len: usize,
+}
+
+// Errors that stop a machine carry the instruction pointer and the raw
+// instruction value (op code including parameter modes) at the point of
+// failure, plus the address involved where there is one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntcodeError {
+    UnknownInstruction {
+        instruction_pointer: usize,
+        code: isize,
+    },
+    UnknownOperation {
+        instruction_pointer: usize,
+        code: isize,
+    },
+    UnknownParameterMode {
+        instruction_pointer: usize,
+        code: isize,
+        address: usize,
+    },
+    NegativeAddress {
+        instruction_pointer: usize,
+        code: isize,
+        address: isize,
+    },
+    // An address or relative base offset too large for an isize
+    AddressOutOfRange {
+        instruction_pointer: usize,
+        code: isize,
+        address: String,
+    },
+    // The relative base plus an offset does not fit in an isize
+    RelativeBaseOverflow {
+        instruction_pointer: usize,
+        code: isize,
+        relative_base: isize,
+        offset: String,
+    },
+    NoInput {
+        instruction_pointer: usize,
+        code: isize,
+    },
+    // Raised by the handler of a custom op code
+    Handler {
+        instruction_pointer: usize,
+        code: isize,
+        message: String,
+    },
+    OpCodeConflict {
+        code: isize,
+        operation: String,
+    },
+    InvalidOpCode {
+        code: isize,
+        reason: String,
+    },
+    Overflow {
+        instruction_pointer: usize,
+        code: isize,
+    },
+    NoProgramLoaded,
+    ReadProgram {
+        path: String,
+        reason: String,
+    },
+    ParseProgram {
+        position: usize,
+        value: String,
+    },
+    Assemble {
+        line: usize,
+        message: String,
+    },
+    Trace {
+        reason: String,
+    },
+    Snapshot {
+        reason: String,
+    },
+    UnknownNetworkAddress {
+        address: isize,
+    },
+    NetworkIdle,
+    NetworkTooLarge {
+        size: usize,
+    },
+    NonAsciiInput {
+        character: char,
+        position: usize,
+    },
+}
+
+/// What ADD and MUL do when the result does not fit in a cell
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Store the wrapped around result
+    #[default]
+    Wrap,
+    /// Stop with `IntcodeError::Overflow`
+    Trap,
+    /// Store the largest or smallest value a cell can hold
+    Saturate,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Status<C = isize> {
+    Output(C),
+    NeedInput,
+    Halted,
+    // An instruction was executed by `step` without any of the above
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint { address: usize, access: Access },
 }
 
-struct Register {
+#[derive(Clone, Debug, Default)]
+pub struct Register<C = isize> {
     halt_flag: bool,
     jump_flag: bool,
+    wait_flag: bool,
+    resume_flag: bool,
     carry_flag: bool,
     sign_flag: bool,
+    overflow_policy: OverflowPolicy,
     instruction_pointer: usize,
-    input_stack: VecDeque<isize>,
+    relative_base: isize,
+    input_stack: VecDeque<C>,
+    output_stack: VecDeque<C>,
 }
 
-pub struct Memory {
-    raw: Vec<isize>,
+// Memory is allocated in pages on first write so that programs can address
+// far beyond their own image without the host allocating all the cells in
+// between. Unwritten cells read as zero.
+#[derive(Clone, Default)]
+pub struct Memory<C = isize> {
+    pages: HashMap<usize, Box<[C]>>,
+    len: usize,
+    cache: Option<DecodeCache<C>>,
 }
 
-type InstructionCall = fn(&mut Memory, &mut Register, &Instruction) -> Option<isize>;
+type InstructionCall<C> = fn(
+    &mut Memory<C>,
+    &mut Register<C>,
+    &mut Io<C>,
+    &Instruction,
+) -> Result<Option<C>, IntcodeError>;
+
+#[derive(Clone)]
+enum Call<C> {
+    Builtin(InstructionCall<C>),
+    Custom(Handler<C>),
+}
 
-struct InstructionSet {
-    op_codes: HashMap<isize, (Operation, usize)>,
-    instructions: HashMap<Operation, InstructionCall>,
+#[derive(Clone)]
+struct OpCode<C> {
+    operation: Operation,
+    arg_len: usize,
+    call: Call<C>,
 }
 
-pub struct Machine {
-    instruction_set: InstructionSet,
-    pub memory: Memory,
-    register: Register,
+// An instruction decoded as far as its own cells allow. Relative arguments
+// also depend on the relative base, so operands are kept raw and resolved
+// to addresses each time the instruction is executed.
+#[derive(Clone)]
+struct Decoded<C> {
+    op_code: usize,
+    operation: Operation,
+    arg_len: usize,
+    modes: [Mode; MAX_ARGS],
+    operands: [C; MAX_ARGS],
 }
 
-impl Default for Register {
-    fn default() -> Register {
-        Register {
-            halt_flag: false,
-            jump_flag: false,
-            carry_flag: false,
-            sign_flag: false,
-            instruction_pointer: 0,
-            input_stack: VecDeque::<isize>::new(),
+// Indexed directly by op code, so decoding and dispatch never hash or
+// allocate
+#[derive(Clone)]
+struct InstructionSet<C = isize> {
+    op_codes: [Option<OpCode<C>>; OP_CODES],
+}
+
+pub struct Machine<C: Cell = isize> {
+    instruction_set: InstructionSet<C>,
+    pub memory: Memory<C>,
+    register: Register<C>,
+    breakpoints: HashSet<usize>,
+    watchpoints: HashSet<usize>,
+    tracer: Option<Box<dyn Write + Send>>,
+    profile: Option<Profile>,
+    code_writes: Option<CodeWrites>,
+    io: Io<C>,
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntcodeError::UnknownInstruction {
+                instruction_pointer,
+                code,
+            } => write!(f, "Unknown instruction {} at {}", code, instruction_pointer),
+            IntcodeError::UnknownOperation {
+                instruction_pointer,
+                code,
+            } => write!(
+                f,
+                "Unknown operation for instruction {} at {}",
+                code, instruction_pointer
+            ),
+            IntcodeError::UnknownParameterMode {
+                instruction_pointer,
+                code,
+                address,
+            } => write!(
+                f,
+                "Unknown parameter mode for address {} in instruction {} at {}",
+                address, code, instruction_pointer
+            ),
+            IntcodeError::NegativeAddress {
+                instruction_pointer,
+                code,
+                address,
+            } => write!(
+                f,
+                "Negative memory address {} in instruction {} at {}",
+                address, code, instruction_pointer
+            ),
+            IntcodeError::AddressOutOfRange {
+                instruction_pointer,
+                code,
+                address,
+            } => write!(
+                f,
+                "Memory address {} out of range in instruction {} at {}",
+                address, code, instruction_pointer
+            ),
+            IntcodeError::RelativeBaseOverflow {
+                instruction_pointer,
+                code,
+                relative_base,
+                offset,
+            } => write!(
+                f,
+                "Relative base {} plus {} out of range in instruction {} at {}",
+                relative_base, offset, code, instruction_pointer
+            ),
+            IntcodeError::NoInput {
+                instruction_pointer,
+                code,
+            } => write!(
+                f,
+                "No input for instruction {} at {}",
+                code, instruction_pointer
+            ),
+            IntcodeError::Handler {
+                instruction_pointer,
+                code,
+                message,
+            } => write!(
+                f,
+                "Instruction {} at {} failed: {}",
+                code, instruction_pointer, message
+            ),
+            IntcodeError::OpCodeConflict { code, operation } => {
+                write!(f, "Op code {} is already used by {}", code, operation)
+            }
+            IntcodeError::InvalidOpCode { code, reason } => {
+                write!(f, "Invalid op code {}: {}", code, reason)
+            }
+            IntcodeError::Overflow {
+                instruction_pointer,
+                code,
+            } => write!(
+                f,
+                "Arithmetic overflow in instruction {} at {}",
+                code, instruction_pointer
+            ),
+            IntcodeError::NoProgramLoaded => write!(f, "No program loaded"),
+            IntcodeError::ReadProgram { path, reason } => {
+                write!(f, "Unable to read program {}: {}", path, reason)
+            }
+            IntcodeError::ParseProgram { position, value } => write!(
+                f,
+                "Unable to parse integer {:?} at position {}",
+                value, position
+            ),
+            IntcodeError::Assemble { line, message } => write!(f, "Line {}: {}", line, message),
+            IntcodeError::Trace { reason } => write!(f, "Unable to write trace: {}", reason),
+            IntcodeError::Snapshot { reason } => write!(f, "Invalid snapshot: {}", reason),
+            IntcodeError::UnknownNetworkAddress { address } => {
+                write!(f, "Packet sent to unknown network address {}", address)
+            }
+            IntcodeError::NetworkIdle => write!(f, "Network is idle with nothing to send"),
+            IntcodeError::NetworkTooLarge { size } => write!(
+                f,
+                "A network of {} machines is too large, address {} is the NAT",
+                size,
+                network::NAT_ADDRESS
+            ),
+            IntcodeError::NonAsciiInput {
+                character,
+                position,
+            } => write!(
+                f,
+                "Input {:?} at position {} is not ASCII",
+                character, position
+            ),
+        }
+    }
+}
+
+impl Error for IntcodeError {}
+
+// The mnemonic used in listings and traces
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Custom(name) => write!(f, "{}", name),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl Operation {
+    /// Index of the argument the operation writes to, if any
+    fn write_arg(self) -> Option<usize> {
+        match self {
+            Operation::ADD | Operation::MUL | Operation::LT | Operation::EQ => Some(2),
+            Operation::IN => Some(0),
+            _ => None,
         }
     }
 }
 
-impl Register {
+impl Instruction {
+    /// Every memory access the instruction makes, in argument order
+    pub fn accesses(&self) -> Vec<(usize, Access)> {
+        let write_arg = self.operation.write_arg();
+        self.args()
+            .iter()
+            .enumerate()
+            .map(|(i, &address)| {
+                if write_arg == Some(i) {
+                    (address, Access::Write)
+                } else {
+                    (address, Access::Read)
+                }
+            })
+            .collect()
+    }
+
+    /// Addresses of the arguments, in order
+    pub fn args(&self) -> &[usize] {
+        &self.args[..self.arg_len]
+    }
+}
+
+// The tracer, input source and output sink are not cloned; a clone starts
+// out untraced and unconnected
+impl<C: Cell> Clone for Machine<C> {
+    fn clone(&self) -> Machine<C> {
+        Machine {
+            instruction_set: self.instruction_set.clone(),
+            memory: self.memory.clone(),
+            register: self.register.clone(),
+            breakpoints: self.breakpoints.clone(),
+            watchpoints: self.watchpoints.clone(),
+            tracer: None,
+            profile: self.profile.clone(),
+            code_writes: self.code_writes.clone(),
+            io: Io::default(),
+        }
+    }
+}
+
+impl Mode {
+    /// Mode of the parameter at `offset` (0-based) of the instruction `code`
+    fn from_code(code: isize, offset: usize) -> Option<Mode> {
+        match (code / MODE_DIVISORS.get(offset)?) % 10 {
+            0 => Some(Mode::Position),
+            1 => Some(Mode::Immediate),
+            2 => Some(Mode::Relative),
+            _ => None,
+        }
+    }
+}
+
+impl<C: Cell> Decoded<C> {
+    fn resolve(&self, m: &Memory<C>, r: &Register<C>) -> Result<Instruction, IntcodeError> {
+        let mut args = [0; MAX_ARGS];
+        for (i, arg) in args.iter_mut().enumerate().take(self.arg_len) {
+            *arg = match self.modes[i] {
+                Mode::Position => m.address(r, &self.operands[i])?,
+                Mode::Immediate => r.instruction_pointer + i + 1,
+                Mode::Relative => {
+                    let (address, overflow) =
+                        C::from_isize(r.relative_base).overflowing_add(&self.operands[i]);
+                    if overflow {
+                        return Err(r.relative_base_overflow(m, &self.operands[i]));
+                    }
+                    m.address(r, &address)?
+                }
+            };
+        }
+
+        Ok(Instruction {
+            operation: self.operation,
+            op_code: self.op_code,
+            args,
+            arg_len: self.arg_len,
+        })
+    }
+}
+
+impl<C: Cell> Register<C> {
     fn halt_flag_set(&self) -> bool {
         self.halt_flag
     }
@@ -84,6 +517,62 @@ impl Register {
         self.jump_flag = false;
     }
 
+    fn wait_flag_set(&self) -> bool {
+        self.wait_flag
+    }
+
+    fn set_wait_flag(&mut self) {
+        self.wait_flag = true;
+    }
+
+    fn clear_wait_flag(&mut self) {
+        self.wait_flag = false;
+    }
+
+    // Set when execution stops at a breakpoint or watchpoint so that the
+    // next instruction runs without stopping at the same place again
+    fn resume_flag_set(&self) -> bool {
+        self.resume_flag
+    }
+
+    fn set_resume_flag(&mut self) {
+        self.resume_flag = true;
+    }
+
+    fn clear_resume_flag(&mut self) {
+        self.resume_flag = false;
+    }
+
+    /// Whether an ADD or MUL has overflowed since the program was loaded
+    /// or the flags were cleared. Results computed since then may not be
+    /// exact.
+    pub fn carry_flag_set(&self) -> bool {
+        self.carry_flag
+    }
+
+    /// Whether the value stored by the last overflowing ADD or MUL was
+    /// negative
+    pub fn sign_flag_set(&self) -> bool {
+        self.sign_flag
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    pub fn instruction_pointer(&self) -> usize {
+        self.instruction_pointer
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.relative_base
+    }
+
+    /// Queued input, in the order it will be read
+    pub fn pending_input(&self) -> Vec<C> {
+        self.input_stack.iter().rev().cloned().collect()
+    }
+
     fn set_carry_flag(&mut self) {
         self.carry_flag = true;
     }
@@ -100,6 +589,37 @@ impl Register {
         self.sign_flag = false;
     }
 
+    // Applies the overflow policy to the result of an arithmetic operation
+    // that overflowed. The carry flag records that it happened and the sign
+    // flag the sign of the value that was stored.
+    fn overflow(
+        &mut self,
+        m: &Memory<C>,
+        wrapped: C,
+        saturated: impl FnOnce() -> C,
+    ) -> Result<C, IntcodeError> {
+        self.set_carry_flag();
+
+        let res = match self.overflow_policy {
+            OverflowPolicy::Wrap => wrapped,
+            OverflowPolicy::Saturate => saturated(),
+            OverflowPolicy::Trap => {
+                return Err(IntcodeError::Overflow {
+                    instruction_pointer: self.instruction_pointer,
+                    code: m.code(self.instruction_pointer),
+                })
+            }
+        };
+
+        if res < C::default() {
+            self.set_sign_flag();
+        } else {
+            self.clear_sign_flag();
+        }
+
+        Ok(res)
+    }
+
     fn incr_instruction_pointer(&mut self, incr: usize) {
         self.instruction_pointer += incr
     }
@@ -108,244 +628,607 @@ impl Register {
         self.instruction_pointer = val
     }
 
-    fn add_input(&mut self, val: isize) {
+    fn adjust_relative_base(&mut self, m: &Memory<C>, val: isize) -> Result<(), IntcodeError> {
+        self.relative_base = self
+            .relative_base
+            .checked_add(val)
+            .ok_or_else(|| self.relative_base_overflow(m, &C::from_isize(val)))?;
+        Ok(())
+    }
+
+    fn relative_base_overflow(&self, m: &Memory<C>, offset: &C) -> IntcodeError {
+        IntcodeError::RelativeBaseOverflow {
+            instruction_pointer: self.instruction_pointer,
+            code: m.code(self.instruction_pointer),
+            relative_base: self.relative_base,
+            offset: offset.to_string(),
+        }
+    }
+
+    fn add_input(&mut self, val: C) {
         self.input_stack.push_front(val)
     }
 
-    fn get_input(&mut self) -> Option<isize> {
+    fn get_input(&mut self) -> Option<C> {
         self.input_stack.pop_back()
     }
-}
 
-impl Default for Memory {
-    fn default() -> Memory {
-        Memory {
-            raw: Vec::<isize>::new(),
-        }
+    fn add_output(&mut self, val: C) {
+        self.output_stack.push_back(val)
+    }
+
+    fn get_output(&mut self) -> Option<C> {
+        self.output_stack.pop_front()
     }
 }
 
-impl Memory {
-    fn init(&mut self, program: &[isize]) {
-        self.raw = program.to_vec();
+impl<C: Cell> Memory<C> {
+    fn init(&mut self, program: &[C]) {
+        self.pages.clear();
+        self.len = 0;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.clear();
+        }
+        for (loc, val) in program.iter().enumerate() {
+            self.set(loc, val.clone());
+        }
     }
 
-    pub fn get(&self, loc: usize) -> isize {
-        if self.raw.len() <= loc {
-            panic!("Memory overflow: {:?}", loc)
+    pub fn get(&self, loc: usize) -> C {
+        match self.pages.get(&(loc / PAGE_SIZE)) {
+            Some(page) => page[loc % PAGE_SIZE].clone(),
+            None => C::default(),
         }
-        self.raw[loc]
     }
 
-    fn get_val_loc(&self, pointer: usize, offset: usize, immediate: bool) -> usize {
-        let location = pointer + offset;
-        if immediate {
-            location
+    // The instruction at `loc` as reported in errors. Values too large for
+    // an isize are never valid instructions, so they are clamped.
+    fn code(&self, loc: usize) -> isize {
+        let code = self.get(loc);
+        code.to_isize().unwrap_or(if code < C::default() {
+            isize::MIN
         } else {
-            self.get(location) as usize
-        }
+            isize::MAX
+        })
     }
 
-    fn set(&mut self, loc: usize, val: isize) {
-        self.raw[loc] = val
+    // An offset to the relative base, which may be negative
+    fn offset(&self, r: &Register<C>, val: &C) -> Result<isize, IntcodeError> {
+        val.to_isize()
+            .ok_or_else(|| IntcodeError::AddressOutOfRange {
+                instruction_pointer: r.instruction_pointer,
+                code: self.code(r.instruction_pointer),
+                address: val.to_string(),
+            })
     }
 
-    fn is_empty(&self) -> bool {
-        self.raw.is_empty()
+    fn address(&self, r: &Register<C>, val: &C) -> Result<usize, IntcodeError> {
+        let val = self.offset(r, val)?;
+        if val < 0 {
+            return Err(IntcodeError::NegativeAddress {
+                instruction_pointer: r.instruction_pointer,
+                code: self.code(r.instruction_pointer),
+                address: val,
+            });
+        }
+        Ok(val as usize)
     }
-}
 
-impl Default for InstructionSet {
-    fn default() -> InstructionSet {
-        InstructionSet {
-            op_codes: HashMap::new(),
-            instructions: HashMap::new(),
+    fn set(&mut self, loc: usize, val: C) {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.invalidate(loc);
         }
+        let page = self
+            .pages
+            .entry(loc / PAGE_SIZE)
+            .or_insert_with(|| vec![C::default(); PAGE_SIZE].into_boxed_slice());
+        page[loc % PAGE_SIZE] = val;
+        self.len = cmp::max(self.len, loc + 1);
+    }
+
+    /// One past the highest address that has been written to
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 }
 
-impl InstructionSet {
+impl<C: Cell> InstructionSet<C> {
     fn insert(
         &mut self,
         code: isize,
-        op: Operation,
+        operation: Operation,
         arg_len: usize,
-        f: fn(&mut Memory, &mut Register, &Instruction) -> Option<isize>,
+        call: InstructionCall<C>,
     ) {
-        self.op_codes.insert(code, (op, arg_len));
-        self.instructions.insert(op, f);
+        self.op_codes[code as usize] = Some(OpCode {
+            operation,
+            arg_len,
+            call: Call::Builtin(call),
+        });
     }
 
-    fn parse(&self, m: &Machine) -> Instruction {
-        let code = m.memory.get(m.register.instruction_pointer);
-
-        let op_code = code % 100;
-        let modes = code / 100;
+    fn op_code(&self, code: isize) -> Option<(usize, &OpCode<C>)> {
+        if code < 0 {
+            return None;
+        }
+        let op_code = (code % 100) as usize;
+        self.op_codes[op_code]
+            .as_ref()
+            .map(|entry| (op_code, entry))
+    }
 
-        let mut mode_v = BitVec::from_elem(3, false);
+    fn decode(&self, code: isize) -> Option<(Operation, usize)> {
+        self.op_code(code)
+            .map(|(_, entry)| (entry.operation, entry.arg_len))
+    }
 
-        mode_v.set(0, modes % 10 == 1);
-        mode_v.set(1, (modes / 10) % 10 == 1);
-        mode_v.set(2, (modes / 100) % 10 == 1);
+    /// Op code and argument count of the operation named `mnemonic`
+    fn lookup(&self, mnemonic: &str) -> Option<(isize, usize)> {
+        self.op_codes
+            .iter()
+            .enumerate()
+            .filter_map(|(code, entry)| entry.as_ref().map(|entry| (code, entry)))
+            .find(|(_, entry)| entry.operation.to_string().eq_ignore_ascii_case(mnemonic))
+            .map(|(code, entry)| (code as isize, entry.arg_len))
+    }
 
-        match self.op_codes.get(&(op_code)) {
-            Some(x) => Instruction {
-                operation: x.0,
-                args: (0..x.1)
-                    .map(|i| {
-                        m.memory
-                            .get_val_loc(m.register.instruction_pointer, i + 1, mode_v[i])
-                    })
-                    .collect(),
-            },
-            None => panic!("Unknown instruction"),
+    fn predecode(&self, m: &Memory<C>, r: &Register<C>) -> Result<Decoded<C>, IntcodeError> {
+        let ip = r.instruction_pointer;
+
+        let (code, (op_code, entry)) = m
+            .get(ip)
+            .to_isize()
+            .and_then(|code| Some((code, self.op_code(code)?)))
+            .ok_or_else(|| IntcodeError::UnknownInstruction {
+                instruction_pointer: ip,
+                code: m.code(ip),
+            })?;
+
+        let mut modes = [Mode::Position; MAX_ARGS];
+        let mut operands: [C; MAX_ARGS] = Default::default();
+        for (i, (mode, operand)) in modes
+            .iter_mut()
+            .zip(operands.iter_mut())
+            .enumerate()
+            .take(entry.arg_len)
+        {
+            *mode = Mode::from_code(code, i).ok_or(IntcodeError::UnknownParameterMode {
+                instruction_pointer: ip,
+                code,
+                address: ip + i + 1,
+            })?;
+            *operand = m.get(ip + i + 1);
         }
+
+        Ok(Decoded {
+            op_code,
+            operation: entry.operation,
+            arg_len: entry.arg_len,
+            modes,
+            operands,
+        })
+    }
+
+    fn parse(&self, m: &Machine<C>) -> Result<Instruction, IntcodeError> {
+        self.predecode(&m.memory, &m.register)?
+            .resolve(&m.memory, &m.register)
     }
 
-    fn execute(&self, m: &mut Memory, r: &mut Register, i: &Instruction) -> Option<isize> {
-        match self.instructions.get(&i.operation) {
-            Some(f) => f(m, r, i),
-            None => panic!("Unknown operation"),
+    fn execute(
+        &self,
+        m: &mut Memory<C>,
+        r: &mut Register<C>,
+        io: &mut Io<C>,
+        i: &Instruction,
+    ) -> Result<Option<C>, IntcodeError> {
+        match self.op_codes[i.op_code].as_ref().map(|entry| &entry.call) {
+            Some(Call::Builtin(f)) => f(m, r, io, i),
+            Some(Call::Custom(handler)) => {
+                let output = handler(&mut Context::new(m, r, io, i))?;
+                if let Some(output) = &output {
+                    io.write(output.clone());
+                }
+                Ok(output)
+            }
+            None => Err(IntcodeError::UnknownOperation {
+                instruction_pointer: r.instruction_pointer,
+                code: m.code(r.instruction_pointer),
+            }),
         }
     }
 }
 
-impl Default for Machine {
-    fn default() -> Machine {
-        let mut instruction_set = InstructionSet::default();
+impl<C: Cell> Default for InstructionSet<C> {
+    fn default() -> InstructionSet<C> {
+        let mut instruction_set = InstructionSet::<C> {
+            op_codes: [(); OP_CODES].map(|_| None),
+        };
 
-        instruction_set.insert(1, Operation::ADD, 3, |m, r, i| {
+        instruction_set.insert(1, Operation::ADD, 3, |m, r, _io, i| {
             let arg1 = m.get(i.args[0]);
             let arg2 = m.get(i.args[1]);
 
-            let (res, overflow) = arg1.overflowing_add(arg2);
+            let (mut res, overflow) = arg1.overflowing_add(&arg2);
             if overflow {
-                // 'res' is the wrapped around value
-                println!("Wrapped: {}", res);
-                r.sign_flag = res < 0;
-                r.set_carry_flag();
+                res = r.overflow(m, res, || arg1.saturating_add(&arg2))?;
             }
             m.set(i.args[2], res);
-            None
+            Ok(None)
         });
 
-        instruction_set.insert(2, Operation::MUL, 3, |m, r, i| {
+        instruction_set.insert(2, Operation::MUL, 3, |m, r, _io, i| {
             let arg1 = m.get(i.args[0]);
             let arg2 = m.get(i.args[1]);
 
-            let (res, overflow) = arg1.overflowing_mul(arg2);
+            let (mut res, overflow) = arg1.overflowing_mul(&arg2);
             if overflow {
-                // 'res' is the wrapped around value
-                println!("Wrapped: {}", res);
-                r.sign_flag = res < 0;
-                r.set_carry_flag();
+                res = r.overflow(m, res, || arg1.saturating_mul(&arg2))?;
             }
             m.set(i.args[2], res);
-            None
+            Ok(None)
         });
 
-        instruction_set.insert(3, Operation::IN, 1, |m, r, i| {
-            m.set(i.args[0], r.get_input().expect("No input"));
-            None
+        instruction_set.insert(3, Operation::IN, 1, |m, r, io, i| {
+            match r.get_input().or_else(|| io.read()) {
+                Some(input) => m.set(i.args[0], input),
+                None => r.set_wait_flag(),
+            }
+            Ok(None)
         });
 
-        instruction_set.insert(4, Operation::OUT, 1, |m, r, i| {
-            if r.carry_flag {}
-            Some(m.get(i.args[0]))
+        instruction_set.insert(4, Operation::OUT, 1, |m, _r, io, i| {
+            let output = m.get(i.args[0]);
+            io.write(output.clone());
+            Ok(Some(output))
         });
 
-        instruction_set.insert(5, Operation::JIT, 2, |m, r, i| {
-            if m.get(i.args[0]) != 0 {
-                r.set_instruction_pointer(m.get(i.args[1]) as usize);
+        instruction_set.insert(5, Operation::JIT, 2, |m, r, _io, i| {
+            if m.get(i.args[0]) != C::default() {
+                r.set_instruction_pointer(m.address(r, &m.get(i.args[1]))?);
                 r.set_jump_flag();
             }
-            None
+            Ok(None)
         });
 
-        instruction_set.insert(6, Operation::JIF, 2, |m, r, i| {
-            if m.get(i.args[0]) == 0 {
-                r.set_instruction_pointer(m.get(i.args[1]) as usize);
+        instruction_set.insert(6, Operation::JIF, 2, |m, r, _io, i| {
+            if m.get(i.args[0]) == C::default() {
+                r.set_instruction_pointer(m.address(r, &m.get(i.args[1]))?);
                 r.set_jump_flag();
             }
-            None
+            Ok(None)
         });
 
-        instruction_set.insert(7, Operation::LT, 3, |m, _r, i| {
-            m.set(i.args[2], (m.get(i.args[0]) < m.get(i.args[1])) as isize);
-            None
+        instruction_set.insert(7, Operation::LT, 3, |m, _r, _io, i| {
+            let res = C::from_isize((m.get(i.args[0]) < m.get(i.args[1])) as isize);
+            m.set(i.args[2], res);
+            Ok(None)
+        });
+
+        instruction_set.insert(8, Operation::EQ, 3, |m, _r, _io, i| {
+            let res = C::from_isize((m.get(i.args[0]) == m.get(i.args[1])) as isize);
+            m.set(i.args[2], res);
+            Ok(None)
         });
 
-        instruction_set.insert(8, Operation::EQ, 3, |m, _r, i| {
-            m.set(i.args[2], (m.get(i.args[0]) == m.get(i.args[1])) as isize);
-            None
+        instruction_set.insert(9, Operation::ARB, 1, |m, r, _io, i| {
+            let offset = m.offset(r, &m.get(i.args[0]))?;
+            r.adjust_relative_base(m, offset)?;
+            Ok(None)
         });
 
-        instruction_set.insert(99, Operation::HALT, 0, |_m, r, _i| {
+        instruction_set.insert(99, Operation::HALT, 0, |_m, r, _io, _i| {
             r.set_halt_flag();
-            None
+            Ok(None)
         });
 
+        instruction_set
+    }
+}
+
+/// Reads a comma separated program from a file
+pub fn read_program<C: Cell>(path: &str) -> Result<Vec<C>, IntcodeError> {
+    let input_str = fs::read_to_string(path).map_err(|e| IntcodeError::ReadProgram {
+        path: path.to_string(),
+        reason: e.to_string(),
+    })?;
+
+    input_str
+        .split(',')
+        .enumerate()
+        .map(|(position, s)| {
+            s.trim()
+                .parse::<C>()
+                .map_err(|_| IntcodeError::ParseProgram {
+                    position,
+                    value: s.to_string(),
+                })
+        })
+        .collect()
+}
+
+impl Default for Machine {
+    fn default() -> Machine {
+        Machine::new()
+    }
+}
+
+impl Machine {
+    /// Starts a machine with additional op codes
+    pub fn builder() -> MachineBuilder {
+        MachineBuilder::default()
+    }
+}
+
+impl<C: Cell> Machine<C> {
+    /// A machine computing with cells of type `C`. `Machine::default()`
+    /// creates one with `isize` cells.
+    pub fn new() -> Machine<C> {
         Machine {
-            instruction_set,
+            instruction_set: InstructionSet::default(),
             memory: Memory::default(),
             register: Register::default(),
+            breakpoints: HashSet::new(),
+            watchpoints: HashSet::new(),
+            tracer: None,
+            profile: None,
+            code_writes: None,
+            io: Io::default(),
         }
     }
-}
 
-impl Machine {
-    pub fn init(&mut self, program: &[isize]) {
+    pub fn init(&mut self, program: &[C]) {
         self.memory.init(program);
-        self.register = Register::default();
+        self.register = Register {
+            overflow_policy: self.register.overflow_policy,
+            ..Register::default()
+        };
     }
 
-    pub fn load_program(&mut self, path: &str) {
-        let input_str = fs::read_to_string(path).expect("Something went wrong reading the file");
-
-        let program: Vec<isize> = input_str
-            .split(',')
-            .map(|s| s.parse::<isize>().expect("Unable to parse integer"))
-            .collect();
-
+    pub fn load_program(&mut self, path: &str) -> Result<(), IntcodeError> {
+        let program = read_program(path)?;
         self.init(&program);
+        Ok(())
     }
 
-    pub fn input(&mut self, input: isize) {
+    pub fn input(&mut self, input: C) {
         self.register.add_input(input)
     }
 
-    pub fn run(&mut self) -> Option<isize> {
+    /// Reads input from `source` whenever the queue filled by `input` is empty
+    pub fn set_input_source(&mut self, source: impl InputSource<C> + Send + 'static) {
+        self.io.set_source(Some(Box::new(source)));
+    }
+
+    /// Passes every output to `sink` as it is produced. Outputs are then no
+    /// longer collected by `run`, but `resume` and `step` still report them.
+    pub fn set_output_sink(&mut self, sink: impl OutputSink<C> + Send + 'static) {
+        self.io.set_sink(Some(Box::new(sink)));
+    }
+
+    /// Disconnects the input source and output sink
+    pub fn clear_io(&mut self) {
+        self.io.set_source(None);
+        self.io.set_sink(None);
+    }
+
+    /// Takes the oldest output collected by `run`
+    pub fn output(&mut self) -> Option<C> {
+        self.register.get_output()
+    }
+
+    /// Takes every output collected by `run`, in the order they were produced
+    pub fn drain_output(&mut self) -> Vec<C> {
+        self.register.output_stack.drain(..).collect()
+    }
+
+    pub fn register(&self) -> &Register<C> {
+        &self.register
+    }
+
+    /// Sets what ADD and MUL do on overflow. The policy is kept when a new
+    /// program is loaded.
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.register.overflow_policy = policy;
+    }
+
+    /// Clears the carry and sign flags, for example before a run whose
+    /// results need to be checked
+    pub fn clear_overflow_flags(&mut self) {
+        self.register.clear_carry_flag();
+        self.register.clear_sign_flag();
+    }
+
+    /// Decodes the instruction about to be executed
+    pub fn next_instruction(&self) -> Result<Instruction, IntcodeError> {
+        self.instruction_set.parse(self)
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// Stops `resume` before any instruction that reads or writes `address`
+    pub fn add_watchpoint(&mut self, address: usize) {
+        self.watchpoints.insert(address);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.remove(&address)
+    }
+
+    fn debug_stop(&mut self, instruction: &Instruction) -> Option<Status<C>> {
+        if self.register.resume_flag_set()
+            || (self.breakpoints.is_empty() && self.watchpoints.is_empty())
+        {
+            return None;
+        }
+
+        let ip = self.register.instruction_pointer;
+
+        let status = if self.breakpoints.contains(&ip) {
+            Some(Status::Breakpoint(ip))
+        } else {
+            instruction
+                .accesses()
+                .into_iter()
+                .find(|(address, _)| self.watchpoints.contains(address))
+                .map(|(address, access)| Status::Watchpoint { address, access })
+        };
+
+        if status.is_some() {
+            self.register.set_resume_flag();
+        }
+
+        status
+    }
+
+    /// Records every executed instruction to `writer`, one line each
+    pub fn trace_to(&mut self, writer: impl Write + Send + 'static) {
+        self.tracer = Some(Box::new(writer));
+    }
+
+    pub fn trace_to_file(&mut self, path: &str) -> Result<(), IntcodeError> {
+        let file = File::create(path).map_err(|e| IntcodeError::Trace {
+            reason: e.to_string(),
+        })?;
+        self.trace_to(BufWriter::new(file));
+        Ok(())
+    }
+
+    /// Flushes and detaches the tracer
+    pub fn stop_trace(&mut self) -> Result<(), IntcodeError> {
+        match self.tracer.take() {
+            Some(mut tracer) => tracer.flush().map_err(|e| IntcodeError::Trace {
+                reason: e.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn execute(&mut self, instruction: &Instruction) -> Result<Option<Status<C>>, IntcodeError> {
+        let ip = self.register.instruction_pointer;
+
+        let mut record = self
+            .tracer
+            .as_ref()
+            .map(|_| trace::Record::new(ip, instruction, &self.memory));
+
+        let output = self.instruction_set.execute(
+            &mut self.memory,
+            &mut self.register,
+            &mut self.io,
+            instruction,
+        )?;
+
+        if self.register.wait_flag_set() {
+            self.register.clear_wait_flag();
+            return Ok(Some(Status::NeedInput));
+        }
+
+        self.register.clear_resume_flag();
+
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record(ip, instruction.operation);
+        }
+
+        if let Some(code_writes) = self.code_writes.as_mut() {
+            code_writes.record(ip, instruction);
+        }
+
+        if let (Some(tracer), Some(record)) = (self.tracer.as_mut(), record.as_mut()) {
+            record.complete(&self.memory, output.clone());
+            writeln!(tracer, "{}", record).map_err(|e| IntcodeError::Trace {
+                reason: e.to_string(),
+            })?;
+        }
+
+        if self.register.halt_flag_set() {
+            self.register.clear_halt_flag();
+            // A halted machine stays halted rather than stopping at a
+            // breakpoint on its HALT again
+            self.register.set_resume_flag();
+            return Ok(Some(Status::Halted));
+        }
+
+        if self.register.jump_flag_set() {
+            self.register.clear_jump_flag();
+        } else {
+            self.register
+                .incr_instruction_pointer(instruction.arg_len + 1);
+        }
+
+        Ok(output.map(Status::Output))
+    }
+
+    /// Executes exactly one instruction, ignoring breakpoints and watchpoints
+    pub fn step(&mut self) -> Result<Status<C>, IntcodeError> {
         if self.memory.is_empty() {
-            panic!("No program loaded");
+            return Err(IntcodeError::NoProgramLoaded);
         }
 
-        let mut final_output: Option<isize> = None;
+        self.timed(|m| {
+            let instruction = m.fetch()?;
 
-        loop {
-            let instruction = self.instruction_set.parse(self);
-
-            if let Some(output) =
-                self.instruction_set
-                    .execute(&mut self.memory, &mut self.register, &instruction)
-            {
-                println!("{:?}", output);
-                final_output = Some(output);
+            Ok(m.execute(&instruction)?.unwrap_or(Status::Stepped))
+        })
+    }
+
+    /// Runs until the program produces an output, needs more input than has
+    /// been queued, halts or reaches a breakpoint or watchpoint. The
+    /// instruction pointer is left where execution stopped, so calling
+    /// `resume` again continues the program.
+    pub fn resume(&mut self) -> Result<Status<C>, IntcodeError> {
+        if self.memory.is_empty() {
+            return Err(IntcodeError::NoProgramLoaded);
+        }
+
+        self.timed(|m| loop {
+            let instruction = m.fetch()?;
+
+            if let Some(status) = m.debug_stop(&instruction) {
+                return Ok(status);
             }
 
-            if self.register.halt_flag_set() {
-                self.register.clear_halt_flag();
-                break;
+            if let Some(status) = m.execute(&instruction)? {
+                return Ok(status);
             }
+        })
+    }
+
+    /// Runs until the program halts, passing over breakpoints and
+    /// watchpoints. Every output is collected and can be taken with `output`
+    /// or `drain_output`; the last one is also returned.
+    pub fn run(&mut self) -> Result<Option<C>, IntcodeError> {
+        let mut final_output: Option<C> = None;
 
-            if self.register.jump_flag_set() {
-                self.register.clear_jump_flag();
-            } else {
-                self.register
-                    .incr_instruction_pointer(instruction.args.len() + 1);
+        loop {
+            match self.resume()? {
+                Status::Output(output) => {
+                    if !self.io.has_sink() {
+                        self.register.add_output(output.clone());
+                    }
+                    final_output = Some(output);
+                }
+                Status::NeedInput => {
+                    return Err(IntcodeError::NoInput {
+                        instruction_pointer: self.register.instruction_pointer,
+                        code: self.memory.code(self.register.instruction_pointer),
+                    })
+                }
+                Status::Halted => break,
+                _ => {}
             }
         }
 
-        final_output
+        Ok(final_output)
     }
 }
 
@@ -353,8 +1236,8 @@ impl Machine {
 fn test_day2_puzzle1() {
     let mut machine = Machine::default();
 
-    machine.load_program("test.txt");
-    machine.run();
+    machine.load_program("test.txt").unwrap();
+    machine.run().unwrap();
 
     assert_eq!(machine.memory.get(0), 4_462_686);
 }
@@ -366,7 +1249,7 @@ fn test_basic_io() {
     machine.input(1337);
     let output = machine.run();
 
-    assert_eq!(output, Some(1337));
+    assert_eq!(output, Ok(Some(1337)));
 }
 
 #[test]
@@ -382,41 +1265,41 @@ fn test_day5_comparison() {
 
     m.init(&p1);
     m.input(8);
-    assert_eq!(m.run(), Some(1));
+    assert_eq!(m.run(), Ok(Some(1)));
 
     m.init(&p1);
     m.input(1337);
-    assert_eq!(m.run(), Some(0));
+    assert_eq!(m.run(), Ok(Some(0)));
 
     // Less than 8, position  mode
 
     m.init(&p2);
     m.input(7);
-    assert_eq!(m.run(), Some(1));
+    assert_eq!(m.run(), Ok(Some(1)));
 
     m.init(&p2);
     m.input(9);
-    assert_eq!(m.run(), Some(0));
+    assert_eq!(m.run(), Ok(Some(0)));
 
     // Equal to 8, immediate mode
 
     m.init(&p3);
     m.input(8);
-    assert_eq!(m.run(), Some(1));
+    assert_eq!(m.run(), Ok(Some(1)));
 
     m.init(&p3);
     m.input(1337);
-    assert_eq!(m.run(), Some(0));
+    assert_eq!(m.run(), Ok(Some(0)));
 
     // Less than 8, immediate mode
 
     m.init(&p4);
     m.input(7);
-    assert_eq!(m.run(), Some(1));
+    assert_eq!(m.run(), Ok(Some(1)));
 
     m.init(&p4);
     m.input(9);
-    assert_eq!(m.run(), Some(0));
+    assert_eq!(m.run(), Ok(Some(0)));
 }
 
 #[test]
@@ -430,21 +1313,21 @@ fn test_day5_jump() {
 
     m.init(&p1);
     m.input(0);
-    assert_eq!(m.run(), Some(0));
+    assert_eq!(m.run(), Ok(Some(0)));
 
     m.init(&p1);
     m.input(1337);
-    assert_eq!(m.run(), Some(1));
+    assert_eq!(m.run(), Ok(Some(1)));
 
     // Immediate mode
 
     m.init(&p2);
     m.input(0);
-    assert_eq!(m.run(), Some(0));
+    assert_eq!(m.run(), Ok(Some(0)));
 
     m.init(&p2);
     m.input(1337);
-    assert_eq!(m.run(), Some(1));
+    assert_eq!(m.run(), Ok(Some(1)));
 }
 
 #[test]
@@ -462,15 +1345,406 @@ fn test_day5_larger_example() {
     // Below 8
     m.init(&p);
     m.input(7);
-    assert_eq!(m.run(), Some(999));
+    assert_eq!(m.run(), Ok(Some(999)));
 
     // Equal to 8
     m.init(&p);
     m.input(8);
-    assert_eq!(m.run(), Some(1000));
+    assert_eq!(m.run(), Ok(Some(1000)));
 
     // Greater than 8
     m.init(&p);
     m.input(9);
-    assert_eq!(m.run(), Some(1001));
+    assert_eq!(m.run(), Ok(Some(1001)));
+}
+
+#[test]
+fn test_day9_quine() {
+    let mut m = Machine::default();
+
+    let p = [
+        109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+    ];
+
+    m.init(&p);
+    assert_eq!(m.run(), Ok(Some(99)));
+    assert_eq!(m.drain_output(), p.to_vec());
+}
+
+#[test]
+fn test_day9_16_digit_output() {
+    let mut m = Machine::default();
+
+    m.init(&[1102, 34_915_192, 34_915_192, 7, 4, 7, 99, 0]);
+    let output = m.run().unwrap().unwrap();
+
+    assert_eq!(output.to_string().len(), 16);
+}
+
+#[test]
+fn test_day9_large_number() {
+    let mut m = Machine::default();
+
+    m.init(&[104, 1_125_899_906_842_624, 99]);
+    assert_eq!(m.run(), Ok(Some(1_125_899_906_842_624)));
+}
+
+#[test]
+fn test_memory_beyond_program() {
+    let mut m = Machine::default();
+
+    // Copy the unwritten cell at 1000 to 0
+    m.init(&[1001, 1000, 0, 0, 99]);
+    m.run().unwrap();
+
+    assert_eq!(m.memory.get(0), 0);
+    assert_eq!(m.memory.get(1000), 0);
+}
+
+#[test]
+fn test_memory_large_address() {
+    let mut m = Machine::default();
+
+    let loc = 1_000_000_000_000;
+
+    m.init(&[1101, 7, 0, loc, 4, loc, 99]);
+
+    assert_eq!(m.run(), Ok(Some(7)));
+    assert_eq!(m.memory.get(loc as usize), 7);
+    assert_eq!(m.memory.len(), loc as usize + 1);
+}
+
+#[test]
+fn test_memory_negative_address() {
+    let mut m = Machine::default();
+
+    m.init(&[4, -1, 99]);
+
+    assert_eq!(
+        m.run(),
+        Err(IntcodeError::NegativeAddress {
+            instruction_pointer: 0,
+            code: 4,
+            address: -1
+        })
+    );
+}
+
+#[test]
+fn test_relative_base_overflow() {
+    for &cache in &[false, true] {
+        let mut m = Machine::default();
+        m.set_decode_cache(cache);
+
+        m.init(&[109, isize::MAX, 109, 1, 99]);
+        assert_eq!(
+            m.run(),
+            Err(IntcodeError::RelativeBaseOverflow {
+                instruction_pointer: 2,
+                code: 109,
+                relative_base: isize::MAX,
+                offset: "1".to_string(),
+            })
+        );
+
+        m.init(&[109, isize::MIN, 109, -1, 99]);
+        assert_eq!(
+            m.run(),
+            Err(IntcodeError::RelativeBaseOverflow {
+                instruction_pointer: 2,
+                code: 109,
+                relative_base: isize::MIN,
+                offset: "-1".to_string(),
+            })
+        );
+
+        // A relative address past the end of an isize is not wrapped around
+        m.init(&[109, isize::MAX, 204, 1, 99]);
+        assert_eq!(
+            m.run(),
+            Err(IntcodeError::RelativeBaseOverflow {
+                instruction_pointer: 2,
+                code: 204,
+                relative_base: isize::MAX,
+                offset: "1".to_string(),
+            })
+        );
+    }
+}
+
+#[test]
+fn test_resume_on_input() {
+    let mut m = Machine::default();
+
+    m.init(&[3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0]);
+
+    assert_eq!(m.resume(), Ok(Status::NeedInput));
+    m.input(2);
+    assert_eq!(m.resume(), Ok(Status::NeedInput));
+    m.input(3);
+    assert_eq!(m.resume(), Ok(Status::Output(5)));
+    assert_eq!(m.resume(), Ok(Status::Halted));
+}
+
+#[test]
+fn test_resume_feedback_loop() {
+    let p = fixtures::FEEDBACK_LOOP;
+    let seq = [9, 8, 7, 6, 5];
+
+    let mut amplifiers: Vec<_> = (0..5).map(|_| Machine::default()).collect();
+
+    for (i, a) in amplifiers.iter_mut().enumerate() {
+        a.init(&p);
+        a.input(seq[i]);
+    }
+
+    let mut signal = 0;
+    let mut halted = false;
+    while !halted {
+        for a in amplifiers.iter_mut() {
+            a.input(signal);
+            match a.resume().unwrap() {
+                Status::Output(output) => signal = output,
+                Status::Halted => halted = true,
+                _ => panic!("Unexpected status"),
+            }
+        }
+    }
+
+    assert_eq!(signal, 139_629_729);
+}
+
+#[test]
+fn test_output_buffer() {
+    let mut m = Machine::default();
+
+    m.init(&[104, 1, 104, 2, 104, 3, 99]);
+    assert_eq!(m.run(), Ok(Some(3)));
+
+    assert_eq!(m.output(), Some(1));
+    assert_eq!(m.drain_output(), vec![2, 3]);
+    assert_eq!(m.output(), None);
+}
+
+#[test]
+fn test_errors() {
+    let mut m = Machine::default();
+
+    assert_eq!(m.run(), Err(IntcodeError::NoProgramLoaded));
+
+    m.init(&[1, 0, 0, 0, 42]);
+    assert_eq!(
+        m.run(),
+        Err(IntcodeError::UnknownInstruction {
+            instruction_pointer: 4,
+            code: 42
+        })
+    );
+
+    m.init(&[104, 1, 304, 0, 99]);
+    assert_eq!(
+        m.run(),
+        Err(IntcodeError::UnknownParameterMode {
+            instruction_pointer: 2,
+            code: 304,
+            address: 3
+        })
+    );
+
+    m.init(&[104, 1, 3, 0, 99]);
+    assert_eq!(
+        m.run(),
+        Err(IntcodeError::NoInput {
+            instruction_pointer: 2,
+            code: 3
+        })
+    );
+
+    // The machine can be reused after an error
+    m.init(&[104, 1, 99]);
+    assert_eq!(m.run(), Ok(Some(1)));
+}
+
+#[test]
+fn test_load_program_errors() {
+    let mut m = Machine::default();
+
+    match m.load_program("missing.txt") {
+        Err(IntcodeError::ReadProgram { path, .. }) => assert_eq!(path, "missing.txt"),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_step() {
+    let mut m = Machine::default();
+
+    m.init(&[1101, 2, 3, 7, 4, 7, 99, 0]);
+
+    let instruction = m.next_instruction().unwrap();
+    assert_eq!(instruction.operation, Operation::ADD);
+    assert_eq!(instruction.args(), &[1, 2, 7]);
+    assert_eq!(m.step(), Ok(Status::Stepped));
+    assert_eq!(m.register().instruction_pointer(), 4);
+    assert_eq!(m.memory.get(7), 5);
+
+    assert_eq!(m.step(), Ok(Status::Output(5)));
+    assert_eq!(m.step(), Ok(Status::Halted));
+    assert_eq!(m.register().instruction_pointer(), 6);
+}
+
+#[test]
+fn test_breakpoint() {
+    let mut m = Machine::default();
+
+    m.init(&[109, 5, 104, 1, 104, 2, 99]);
+    m.add_breakpoint(4);
+
+    assert_eq!(m.resume(), Ok(Status::Output(1)));
+    assert_eq!(m.resume(), Ok(Status::Breakpoint(4)));
+    assert_eq!(m.register().relative_base(), 5);
+    assert_eq!(m.resume(), Ok(Status::Output(2)));
+    assert_eq!(m.resume(), Ok(Status::Halted));
+
+    // Breakpoints do not interrupt a full run
+    m.init(&[109, 5, 104, 1, 104, 2, 99]);
+    assert_eq!(m.run(), Ok(Some(2)));
+
+    assert!(m.remove_breakpoint(4));
+    m.init(&[109, 5, 104, 1, 104, 2, 99]);
+    assert_eq!(m.resume(), Ok(Status::Output(1)));
+    assert_eq!(m.resume(), Ok(Status::Output(2)));
+}
+
+#[test]
+fn test_breakpoint_on_halt() {
+    let mut m = Machine::default();
+
+    m.init(&[99]);
+    m.add_breakpoint(0);
+
+    assert_eq!(m.resume(), Ok(Status::Breakpoint(0)));
+    assert_eq!(m.resume(), Ok(Status::Halted));
+    assert_eq!(m.resume(), Ok(Status::Halted));
+    assert_eq!(m.resume(), Ok(Status::Halted));
+
+    m.init(&[99]);
+    assert_eq!(m.resume(), Ok(Status::Breakpoint(0)));
+}
+
+#[test]
+fn test_watchpoint() {
+    let mut m = Machine::default();
+
+    m.init(&[3, 9, 1001, 9, 1, 10, 4, 10, 99, 0, 0]);
+    m.add_watchpoint(9);
+    m.input(41);
+
+    assert_eq!(
+        m.resume(),
+        Ok(Status::Watchpoint {
+            address: 9,
+            access: Access::Write
+        })
+    );
+    assert_eq!(m.register().pending_input(), vec![41]);
+    assert_eq!(
+        m.resume(),
+        Ok(Status::Watchpoint {
+            address: 9,
+            access: Access::Read
+        })
+    );
+    assert_eq!(m.register().instruction_pointer(), 2);
+    assert_eq!(m.resume(), Ok(Status::Output(42)));
+}
+
+#[test]
+fn test_trace() {
+    let mut m = Machine::default();
+
+    let path = std::env::temp_dir().join("intcode_test_trace.txt");
+    let path = path.to_str().unwrap();
+
+    m.init(&[3, 9, 1001, 9, 1, 10, 4, 10, 99, 0, 0]);
+    m.trace_to_file(path).unwrap();
+    m.input(41);
+    m.run().unwrap();
+    m.stop_trace().unwrap();
+
+    assert_eq!(
+        fs::read_to_string(path).unwrap(),
+        "ip=0 op=IN args=9 read=- write=41 out=-\n\
+         ip=2 op=ADD args=9,4,10 read=41,1 write=42 out=-\n\
+         ip=6 op=OUT args=10 read=42 write=- out=42\n\
+         ip=8 op=HALT args=- read=- write=- out=-\n"
+    );
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_clone() {
+    let mut m = Machine::default();
+
+    m.init(&[3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0]);
+    m.input(2);
+    assert_eq!(m.resume(), Ok(Status::NeedInput));
+
+    let mut other = m.clone();
+
+    m.input(3);
+    other.input(40);
+
+    assert_eq!(m.run(), Ok(Some(5)));
+    assert_eq!(other.run(), Ok(Some(42)));
+}
+
+#[test]
+fn test_overflow_policy() {
+    let add = [1, 5, 6, 7, 99, isize::MAX, 1, 0];
+    let mul = [2, 5, 6, 7, 99, isize::MIN, 2, 0];
+
+    let mut m = Machine::default();
+    assert_eq!(m.register().overflow_policy(), OverflowPolicy::Wrap);
+
+    m.init(&add);
+    assert_eq!(m.run(), Ok(None));
+    assert_eq!(m.memory.get(7), isize::MIN);
+    assert!(m.register().carry_flag_set());
+    assert!(m.register().sign_flag_set());
+
+    m.clear_overflow_flags();
+    assert!(!m.register().carry_flag_set());
+    assert!(!m.register().sign_flag_set());
+
+    m.set_overflow_policy(OverflowPolicy::Saturate);
+    m.init(&add);
+    assert_eq!(m.run(), Ok(None));
+    assert_eq!(m.memory.get(7), isize::MAX);
+    assert!(m.register().carry_flag_set());
+    assert!(!m.register().sign_flag_set());
+
+    m.init(&mul);
+    assert_eq!(m.run(), Ok(None));
+    assert_eq!(m.memory.get(7), isize::MIN);
+    assert!(m.register().sign_flag_set());
+
+    m.set_overflow_policy(OverflowPolicy::Trap);
+    m.init(&mul);
+    assert_eq!(
+        m.run(),
+        Err(IntcodeError::Overflow {
+            instruction_pointer: 0,
+            code: 2,
+        })
+    );
+    assert_eq!(m.memory.get(7), 0);
+    assert!(m.register().carry_flag_set());
+
+    // Results that fit leave the flags alone
+    m.init(&[1101, 2, 3, 5, 99, 0]);
+    assert_eq!(m.run(), Ok(None));
+    assert_eq!(m.memory.get(5), 5);
+    assert!(!m.register().carry_flag_set());
 }
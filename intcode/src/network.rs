@@ -0,0 +1,286 @@
+use crate::{IntcodeError, Machine, Status};
+use std::collections::VecDeque;
+
+/// Packets sent to this address go to the NAT instead of a machine
+pub const NAT_ADDRESS: isize = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub x: isize,
+    pub y: isize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A packet was sent to the NAT
+    Nat(Packet),
+    /// The network was idle and the NAT sent its last packet to address 0
+    Wake(Packet),
+}
+
+/// `isize` machines that exchange packets of (address, X, Y) outputs, scheduled
+/// round robin on the calling thread so that runs are reproducible.
+///
+/// Each machine gets its address as its first input. After that, whenever it
+/// asks for input it gets the X and Y of the next packet in its queue, or -1
+/// if the queue is empty. In every round each machine runs until it asks for
+/// input again. Packets for a machine that has halted are dropped.
+pub struct Network {
+    machines: Vec<Machine>,
+    halted: Vec<bool>,
+    queues: Vec<VecDeque<Packet>>,
+    partial: Vec<Vec<isize>>,
+    nat: Option<Packet>,
+}
+
+impl Network {
+    /// A network of `size` machines running `program`. Machines get the
+    /// addresses below `NAT_ADDRESS`, so there can be at most that many.
+    pub fn new(program: &[isize], size: usize) -> Result<Network, IntcodeError> {
+        if size > NAT_ADDRESS as usize {
+            return Err(IntcodeError::NetworkTooLarge { size });
+        }
+
+        let machines = (0..size)
+            .map(|address| {
+                let mut m = Machine::default();
+                m.init(program);
+                m.input(address as isize);
+                m
+            })
+            .collect();
+
+        Ok(Network {
+            machines,
+            halted: vec![false; size],
+            queues: vec![VecDeque::new(); size],
+            partial: vec![Vec::new(); size],
+            nat: None,
+        })
+    }
+
+    /// The last packet the NAT received
+    pub fn nat_packet(&self) -> Option<Packet> {
+        self.nat
+    }
+
+    fn route(&mut self, output: &[isize], events: &mut Vec<Event>) -> Result<(), IntcodeError> {
+        let packet = Packet {
+            x: output[1],
+            y: output[2],
+        };
+
+        match output[0] {
+            NAT_ADDRESS => {
+                self.nat = Some(packet);
+                events.push(Event::Nat(packet));
+            }
+            address if address >= 0 && (address as usize) < self.queues.len() => {
+                if !self.halted[address as usize] {
+                    self.queues[address as usize].push_back(packet)
+                }
+            }
+            address => return Err(IntcodeError::UnknownNetworkAddress { address }),
+        }
+
+        Ok(())
+    }
+
+    /// Runs machine `i` until it asks for input. Returns true if it received
+    /// or sent anything.
+    fn service(&mut self, i: usize, events: &mut Vec<Event>) -> Result<bool, IntcodeError> {
+        if self.halted[i] {
+            return Ok(false);
+        }
+
+        // Booting counts as activity; the address is still queued then
+        let mut active = !self.machines[i].register().pending_input().is_empty();
+
+        if !active {
+            match self.queues[i].pop_front() {
+                Some(packet) => {
+                    self.machines[i].input(packet.x);
+                    self.machines[i].input(packet.y);
+                    active = true;
+                }
+                None => self.machines[i].input(-1),
+            }
+        }
+
+        loop {
+            match self.machines[i].resume()? {
+                Status::Output(output) => {
+                    active = true;
+                    self.partial[i].push(output);
+                    if self.partial[i].len() == 3 {
+                        let packet: Vec<isize> = self.partial[i].drain(..).collect();
+                        self.route(&packet, events)?;
+                    }
+                }
+                Status::NeedInput => break,
+                Status::Halted => {
+                    self.halted[i] = true;
+                    self.queues[i].clear();
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(active)
+    }
+
+    /// Runs one round over every machine. If no machine received or sent
+    /// anything the network is idle, and the NAT sends its last packet to
+    /// address 0. An idle network that the NAT cannot wake, because it has no
+    /// packet or machine 0 has halted, is an error.
+    pub fn step(&mut self) -> Result<Vec<Event>, IntcodeError> {
+        let mut events = Vec::new();
+        let mut active = false;
+
+        for i in 0..self.machines.len() {
+            active |= self.service(i, &mut events)?;
+        }
+
+        if !active && self.queues.iter().all(|q| q.is_empty()) {
+            match self.nat {
+                Some(packet) if self.halted.first() == Some(&false) => {
+                    self.queues[0].push_back(packet);
+                    events.push(Event::Wake(packet));
+                }
+                _ => return Err(IntcodeError::NetworkIdle),
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Runs until the first packet is sent to the NAT
+    pub fn first_nat_packet(&mut self) -> Result<Packet, IntcodeError> {
+        loop {
+            for event in self.step()? {
+                if let Event::Nat(packet) = event {
+                    return Ok(packet);
+                }
+            }
+        }
+    }
+
+    /// Runs until the NAT wakes the network with the same Y value twice in a
+    /// row, and returns that value
+    pub fn first_repeated_wake(&mut self) -> Result<isize, IntcodeError> {
+        let mut last_y = None;
+        loop {
+            for event in self.step()? {
+                if let Event::Wake(packet) = event {
+                    if last_y == Some(packet.y) {
+                        return Ok(packet.y);
+                    }
+                    last_y = Some(packet.y);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn relay_program() -> Vec<isize> {
+    // Machine 0 starts a packet down the chain 0 -> 1 -> 2 -> 3, each machine
+    // incrementing X and Y. Machine 3 sends X and a Y of 42 to the NAT.
+    crate::asm::assemble(
+        "
+                IN addr
+                JIT addr, #recv
+                OUT #1
+                OUT #1
+                OUT #100
+        recv:   IN x
+                EQ x, #-1, t
+                JIT t, #recv
+                IN y
+                EQ addr, #3, t
+                JIT t, #nat
+                ADD addr, #1, t
+                OUT t
+                ADD x, #1, x
+                OUT x
+                ADD y, #1, y
+                OUT y
+                JIT #1, #recv
+        nat:    OUT #255
+                OUT x
+                OUT #42
+                JIT #1, #recv
+        addr:   DATA 0
+        x:      DATA 0
+        y:      DATA 0
+        t:      DATA 0
+        ",
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_first_nat_packet() {
+    let mut network = Network::new(&relay_program(), 4).unwrap();
+
+    assert_eq!(network.first_nat_packet(), Ok(Packet { x: 3, y: 42 }));
+}
+
+#[test]
+fn test_nat_wakes_idle_network() {
+    let mut network = Network::new(&relay_program(), 4).unwrap();
+
+    assert_eq!(network.first_repeated_wake(), Ok(42));
+    assert_eq!(network.nat_packet(), Some(Packet { x: 6, y: 42 }));
+}
+
+#[test]
+fn test_idle_without_nat_packet() {
+    // Every machine just polls for input
+    let mut network = Network::new(&[3, 5, 1105, 1, 0, 0], 2).unwrap();
+
+    assert_eq!(network.step(), Ok(vec![]));
+    assert_eq!(network.step(), Err(IntcodeError::NetworkIdle));
+}
+
+#[test]
+fn test_network_size() {
+    assert!(Network::new(&[99], 255).is_ok());
+    assert_eq!(
+        Network::new(&[99], 256).err(),
+        Some(IntcodeError::NetworkTooLarge { size: 256 })
+    );
+}
+
+#[test]
+fn test_packet_to_halted_machine() {
+    // Machine 1 halts at once while machine 0 sends it two packets
+    let program = crate::asm::assemble(
+        "
+                IN addr
+                JIT addr, #done
+                OUT #1
+                OUT #5
+                OUT #6
+                IN x
+                OUT #1
+                OUT #7
+                OUT #8
+        loop:   IN x
+                JIT #1, #loop
+        done:   HALT
+        addr:   DATA 0
+        x:      DATA 0
+        ",
+    )
+    .unwrap();
+
+    let mut network = Network::new(&program, 2).unwrap();
+    assert_eq!(network.step(), Ok(vec![]));
+    assert_eq!(network.step(), Ok(vec![]));
+    assert_eq!(network.step(), Err(IntcodeError::NetworkIdle));
+
+    let mut network = Network::new(&program, 2).unwrap();
+    assert_eq!(network.first_nat_packet(), Err(IntcodeError::NetworkIdle));
+}
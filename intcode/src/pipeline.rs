@@ -0,0 +1,140 @@
+use crate::{IntcodeError, Machine, Status};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topology {
+    /// Every stage runs once, feeding its outputs to the next stage
+    Linear,
+    /// The outputs of the last stage are fed back to the first stage until
+    /// the last stage halts
+    Feedback,
+}
+
+/// A chain of `isize` machines running the same program, each configured by a
+/// phase setting given as its first input
+pub struct Pipeline {
+    program: Vec<isize>,
+    phases: Vec<isize>,
+    topology: Topology,
+}
+
+impl Pipeline {
+    pub fn new(program: &[isize], phases: &[isize], topology: Topology) -> Pipeline {
+        Pipeline {
+            program: program.to_vec(),
+            phases: phases.to_vec(),
+            topology,
+        }
+    }
+
+    /// Feeds `input` to the first stage and runs until the last stage halts.
+    /// Returns the last signal produced by the last stage.
+    pub fn run(&self, input: isize) -> Result<Option<isize>, IntcodeError> {
+        let mut stages: Vec<Machine> = self
+            .phases
+            .iter()
+            .map(|phase| {
+                let mut m = Machine::default();
+                m.init(&self.program);
+                m.input(*phase);
+                m
+            })
+            .collect();
+
+        let last = match stages.len() {
+            0 => return Ok(Some(input)),
+            len => len - 1,
+        };
+
+        let no_input = |m: &Machine| {
+            let ip = m.register().instruction_pointer();
+            IntcodeError::NoInput {
+                instruction_pointer: ip,
+                code: m.memory.get(ip),
+            }
+        };
+
+        let mut signals = vec![input];
+        let mut final_signal = None;
+
+        loop {
+            let mut progress = false;
+            let mut waiting = None;
+
+            for (i, m) in stages.iter_mut().enumerate() {
+                for signal in signals.drain(..) {
+                    m.input(signal);
+                }
+
+                let halted = loop {
+                    match m.resume()? {
+                        Status::Output(output) => {
+                            progress = true;
+                            signals.push(output);
+                        }
+                        Status::NeedInput => break false,
+                        Status::Halted => break true,
+                        _ => {}
+                    }
+                };
+
+                if i == last {
+                    if let Some(signal) = signals.last() {
+                        final_signal = Some(*signal);
+                    }
+                    if halted {
+                        return Ok(final_signal);
+                    }
+                }
+
+                if !halted {
+                    if self.topology == Topology::Linear {
+                        return Err(no_input(m));
+                    }
+                    waiting = waiting.or_else(|| Some(no_input(m)));
+                }
+            }
+
+            // A whole round without any signals means every stage is stuck
+            if !progress {
+                if let Some(e) = waiting {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_linear() {
+    let p = [
+        3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+    ];
+
+    let pipeline = Pipeline::new(&p, &[4, 3, 2, 1, 0], Topology::Linear);
+
+    assert_eq!(pipeline.run(0), Ok(Some(43210)));
+}
+
+#[test]
+fn test_feedback() {
+    let p = crate::fixtures::FEEDBACK_LOOP;
+    let pipeline = Pipeline::new(&p, &[9, 8, 7, 6, 5], Topology::Feedback);
+
+    assert_eq!(pipeline.run(0), Ok(Some(139_629_729)));
+}
+
+#[test]
+fn test_starved_stage() {
+    // Every stage wants two signals after its phase setting
+    let p = [3, 9, 3, 9, 3, 9, 4, 9, 99, 0];
+
+    let pipeline = Pipeline::new(&p, &[1, 2], Topology::Feedback);
+
+    assert_eq!(
+        pipeline.run(0),
+        Err(IntcodeError::NoInput {
+            instruction_pointer: 4,
+            code: 3
+        })
+    );
+}
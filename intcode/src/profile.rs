@@ -0,0 +1,196 @@
+// While profiling, a machine counts every instruction it completes, by
+// operation and by address, and adds up the time spent in `step` and
+// `resume`. An instruction that has to wait for input is counted once, when
+// it finally runs.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::{Cell, Machine, Operation};
+
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    pub operations: HashMap<Operation, usize>,
+    pub addresses: HashMap<usize, usize>,
+    pub instructions: usize,
+    pub elapsed: Duration,
+}
+
+impl Profile {
+    pub(crate) fn record(&mut self, address: usize, operation: Operation) {
+        *self.operations.entry(operation).or_insert(0) += 1;
+        *self.addresses.entry(address).or_insert(0) += 1;
+        self.instructions += 1;
+    }
+
+    /// Instruction addresses with their hit counts, most hit first
+    pub fn hottest(&self) -> Vec<(usize, usize)> {
+        let mut hottest: Vec<_> = self.addresses.iter().map(|(a, n)| (*a, *n)).collect();
+        hottest.sort_by(|(a, m), (b, n)| n.cmp(m).then(a.cmp(b)));
+        hottest
+    }
+
+    /// 0 if nothing has run yet
+    pub fn instructions_per_second(&self) -> f64 {
+        if self.elapsed.as_nanos() == 0 {
+            return 0.0;
+        }
+        self.instructions as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Summarises the profile, listing the `top` most hit addresses with the
+    /// instruction now in the memory of `m` at each of them
+    pub fn report<C: Cell>(&self, m: &Machine<C>, top: usize) -> String {
+        let mut s = String::new();
+
+        writeln!(
+            s,
+            "{} instructions in {:?} ({:.0} per second)",
+            self.instructions,
+            self.elapsed,
+            self.instructions_per_second()
+        )
+        .unwrap();
+
+        let mut operations: Vec<_> = self.operations.iter().collect();
+        operations.sort_by(|(a, m), (b, n)| n.cmp(m).then(a.to_string().cmp(&b.to_string())));
+
+        writeln!(s, "\noperations:").unwrap();
+        for (operation, count) in operations {
+            writeln!(
+                s,
+                "{:>12} {:>5.1}% {}",
+                count,
+                100.0 * *count as f64 / self.instructions.max(1) as f64,
+                operation
+            )
+            .unwrap();
+        }
+
+        writeln!(s, "\nhottest addresses:").unwrap();
+        for (address, count) in self.hottest().into_iter().take(top) {
+            let line = &m.disassemble(address, 1)[0];
+            writeln!(s, "{:>12} {}", count, line).unwrap();
+        }
+
+        s
+    }
+}
+
+impl<C: Cell> Machine<C> {
+    /// Starts counting executed instructions, discarding any earlier profile
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Stops profiling and returns what was counted
+    pub fn stop_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    // Runs `f`, adding the time it takes to the profile if there is one
+    pub(crate) fn timed<T>(&mut self, f: impl FnOnce(&mut Machine<C>) -> T) -> T {
+        let start = self.profile.as_ref().map(|_| Instant::now());
+        let result = f(self);
+        if let (Some(profile), Some(start)) = (self.profile.as_mut(), start) {
+            profile.elapsed += start.elapsed();
+        }
+        result
+    }
+}
+
+#[test]
+fn test_profile() {
+    // Counts down from the input, running the inner loop once for every
+    // number on the way, so it is hit far more often than the outer loop
+    let program = crate::asm::assemble(
+        "
+                IN n
+        outer:  ADD n, #0, i
+        inner:  ADD i, #-1, i
+                JIT i, #inner
+                ADD n, #-1, n
+                JIT n, #outer
+                OUT n
+                HALT
+        n:      DATA 0
+        i:      DATA 0
+        ",
+    )
+    .unwrap();
+
+    let mut m = Machine::default();
+    m.init(&program);
+    m.input(4);
+    m.run().unwrap();
+    assert!(m.profile().is_none());
+
+    m.start_profile();
+    m.init(&program);
+    m.resume().unwrap();
+    m.input(4);
+    m.run().unwrap();
+
+    let profile = m.stop_profile().unwrap();
+    assert!(m.profile().is_none());
+
+    assert_eq!(profile.instructions, 35);
+    assert_eq!(profile.operations[&Operation::IN], 1);
+    assert_eq!(profile.operations[&Operation::ADD], 18);
+    assert_eq!(profile.operations[&Operation::JIT], 14);
+    assert_eq!(profile.operations[&Operation::HALT], 1);
+    assert_eq!(
+        profile.hottest(),
+        vec![
+            (6, 10),
+            (10, 10),
+            (2, 4),
+            (13, 4),
+            (17, 4),
+            (0, 1),
+            (20, 1),
+            (22, 1)
+        ]
+    );
+    assert!(profile.elapsed > Duration::from_secs(0));
+
+    let report = profile.report(&m, 3);
+    let lines: Vec<&str> = report.lines().collect();
+    assert!(lines[0].starts_with("35 instructions in "));
+    assert_eq!(
+        &lines[1..],
+        &[
+            "",
+            "operations:",
+            "          18  51.4% ADD",
+            "          14  40.0% JIT",
+            "           1   2.9% HALT",
+            "           1   2.9% IN",
+            "           1   2.9% OUT",
+            "",
+            "hottest addresses:",
+            "          10     6: ADD 24, #-1, 24          ; 1001,24,-1,24",
+            "          10    10: JIT 24, #6               ; 1005,24,6",
+            "           4     2: ADD 23, #0, 24           ; 1001,23,0,24",
+        ]
+    );
+}
+
+#[test]
+fn test_empty_profile() {
+    let mut m = Machine::default();
+    m.init(&[99]);
+    m.start_profile();
+
+    let profile = m.profile().unwrap();
+    assert_eq!(profile.instructions_per_second(), 0.0);
+    assert_eq!(
+        profile.report(&m, 2),
+        "0 instructions in 0ns (0 per second)\n\noperations:\n\nhottest addresses:\n"
+    );
+}
@@ -0,0 +1,232 @@
+// While enabled, a machine notes which cells it executes as part of an
+// instruction and which cells ADD, MUL, LT, EQ and IN write to. A write to a
+// cell that is executed before or after it is self-modifying code. Writes
+// are kept per writing instruction and cell, with the first and last time
+// they happened, so the log stays small however long the program runs.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::hash::Hash;
+
+use crate::{Cell, Instruction, Machine};
+
+// First and last step at which something happened, and how often
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Span {
+    first: usize,
+    last: usize,
+    count: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CodeWrites {
+    step: usize,
+    executed: HashMap<usize, Span>,
+    writes: HashMap<(usize, usize), Span>,
+}
+
+/// An instruction writing to a cell that is executed as code
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodeWrite {
+    /// Address of the writing instruction
+    pub writer: usize,
+    pub address: usize,
+    pub count: usize,
+    /// Whether the cell had been executed before it was written, which
+    /// includes an instruction overwriting one of its own operands
+    pub after_execution: bool,
+    /// Whether the cell was executed after it was written
+    pub before_execution: bool,
+}
+
+impl Span {
+    fn new(step: usize) -> Span {
+        Span {
+            first: step,
+            last: step,
+            count: 1,
+        }
+    }
+
+    fn extend(&mut self, step: usize) {
+        self.last = step;
+        self.count += 1;
+    }
+}
+
+fn note<K: Eq + Hash>(spans: &mut HashMap<K, Span>, key: K, step: usize) {
+    spans
+        .entry(key)
+        .and_modify(|span| span.extend(step))
+        .or_insert_with(|| Span::new(step));
+}
+
+impl CodeWrites {
+    pub(crate) fn record(&mut self, ip: usize, instruction: &Instruction) {
+        self.step += 1;
+
+        for address in ip..=ip + instruction.args().len() {
+            note(&mut self.executed, address, self.step);
+        }
+
+        if let Some(i) = instruction.operation.write_arg() {
+            note(&mut self.writes, (ip, instruction.args()[i]), self.step);
+        }
+    }
+
+    /// Every write into a cell that was executed, ordered by writer and
+    /// address
+    pub fn code_writes(&self) -> Vec<CodeWrite> {
+        let mut writes: Vec<CodeWrite> = self
+            .writes
+            .iter()
+            .filter_map(|(&(writer, address), write)| {
+                let executed = self.executed.get(&address)?;
+                Some(CodeWrite {
+                    writer,
+                    address,
+                    count: write.count,
+                    after_execution: executed.first <= write.last,
+                    before_execution: executed.last > write.first,
+                })
+            })
+            .collect();
+        writes.sort_by_key(|w| (w.writer, w.address));
+        writes
+    }
+
+    /// Lists every write into code next to the instruction now in the memory
+    /// of `m` at the writer's address
+    pub fn report<C: Cell>(&self, m: &Machine<C>) -> String {
+        let mut s = String::new();
+
+        for write in self.code_writes() {
+            let when = match (write.after_execution, write.before_execution) {
+                (true, true) => "already executed and executed again",
+                (true, false) => "already executed",
+                _ => "executed later",
+            };
+            let line = &m.disassemble(write.writer, 1)[0];
+            writeln!(
+                s,
+                "{} writes {} {}x, {}",
+                line, write.address, write.count, when
+            )
+            .unwrap();
+        }
+
+        s
+    }
+}
+
+impl<C: Cell> Machine<C> {
+    /// Starts logging writes to code, discarding any earlier log
+    pub fn start_code_writes(&mut self) {
+        self.code_writes = Some(CodeWrites::default());
+    }
+
+    pub fn code_writes(&self) -> Option<&CodeWrites> {
+        self.code_writes.as_ref()
+    }
+
+    pub fn stop_code_writes(&mut self) -> Option<CodeWrites> {
+        self.code_writes.take()
+    }
+}
+
+#[test]
+fn test_code_writes_day2() {
+    let mut m = Machine::default();
+    m.start_code_writes();
+    m.init(&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+    m.run().unwrap();
+
+    let log = m.stop_code_writes().unwrap();
+    assert_eq!(
+        log.code_writes(),
+        vec![
+            CodeWrite {
+                writer: 0,
+                address: 3,
+                count: 1,
+                after_execution: true,
+                before_execution: false,
+            },
+            CodeWrite {
+                writer: 4,
+                address: 0,
+                count: 1,
+                after_execution: true,
+                before_execution: false,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_code_writes_patch_ahead() {
+    // Reads an op code and patches it into the next instruction
+    let mut m = Machine::default();
+    m.start_code_writes();
+    m.init(&[3, 2, 0, 9, 99]);
+    m.input(104);
+    assert_eq!(m.run(), Ok(Some(9)));
+
+    let log = m.code_writes().unwrap();
+    assert_eq!(
+        log.code_writes(),
+        vec![CodeWrite {
+            writer: 0,
+            address: 2,
+            count: 1,
+            after_execution: false,
+            before_execution: true,
+        }]
+    );
+    assert_eq!(
+        log.report(&m),
+        "    0: IN 2                     ; 3,2 writes 2 1x, executed later\n"
+    );
+}
+
+#[test]
+fn test_code_writes_loop() {
+    // Counts down by rewriting the immediate operand of its own OUT
+    let program = crate::asm::assemble(
+        "
+        loop:   OUT #3
+                ADD loop+1, #-1, loop+1
+                JIT loop+1, #loop
+                HALT
+        ",
+    )
+    .unwrap();
+
+    let mut m = Machine::default();
+    m.start_code_writes();
+    m.init(&program);
+    m.run().unwrap();
+    assert_eq!(m.drain_output(), vec![3, 2, 1]);
+
+    let log = m.code_writes().unwrap();
+    assert_eq!(
+        log.code_writes(),
+        vec![CodeWrite {
+            writer: 2,
+            address: 1,
+            count: 3,
+            after_execution: true,
+            before_execution: true,
+        }]
+    );
+    assert_eq!(
+        log.report(&m),
+        "    2: ADD 1, #-1, 1            ; 1001,1,-1,1 writes 1 3x, already executed and executed again\n"
+    );
+
+    // Writes to data are not reported
+    m.start_code_writes();
+    m.init(&[1101, 1, 2, 5, 99, 0]);
+    m.run().unwrap();
+    assert!(m.code_writes().unwrap().code_writes().is_empty());
+}
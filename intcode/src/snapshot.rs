@@ -0,0 +1,238 @@
+// Machine state is saved as text, one field per line. Memory is written as
+// the pages that have been allocated so that snapshots of programs using
+// large addresses stay small.
+//
+// intcode-snapshot 1
+// instruction_pointer 12
+// relative_base 0
+// flags 0 0 0 0 0 0
+// input 5,6
+// output -
+// len 1000
+// page 0 3,225,1,225,...
+
+use crate::cache::DecodeCache;
+use crate::{Cell, IntcodeError, Machine, Memory, Register, PAGE_SIZE};
+use std::fmt::Write;
+use std::fs;
+use std::str::FromStr;
+
+const HEADER: &str = "intcode-snapshot 1";
+
+fn error(reason: String) -> IntcodeError {
+    IntcodeError::Snapshot { reason }
+}
+
+fn join<'a, C: Cell>(values: impl Iterator<Item = &'a C>) -> String {
+    let values: Vec<String> = values.map(|v| v.to_string()).collect();
+    if values.is_empty() {
+        "-".to_string()
+    } else {
+        values.join(",")
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, IntcodeError> {
+    value
+        .parse::<T>()
+        .map_err(|_| error(format!("Invalid number {:?}", value)))
+}
+
+fn parse_list<C: Cell>(value: &str) -> Result<Vec<C>, IntcodeError> {
+    if value == "-" {
+        return Ok(Vec::new());
+    }
+    value.split(',').map(parse).collect()
+}
+
+impl<C: Cell> Machine<C> {
+    /// Captures memory, registers and pending input and output
+    pub fn snapshot(&self) -> String {
+        let r = &self.register;
+        let flags = [
+            r.halt_flag,
+            r.jump_flag,
+            r.wait_flag,
+            r.resume_flag,
+            r.carry_flag,
+            r.sign_flag,
+        ];
+
+        let mut s = String::new();
+
+        // Writing to a String cannot fail
+        writeln!(s, "{}", HEADER).unwrap();
+        writeln!(s, "instruction_pointer {}", r.instruction_pointer).unwrap();
+        writeln!(s, "relative_base {}", r.relative_base).unwrap();
+        writeln!(
+            s,
+            "flags {}",
+            flags
+                .iter()
+                .map(|&f| (f as u8).to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        )
+        .unwrap();
+        writeln!(s, "input {}", join(r.input_stack.iter().rev())).unwrap();
+        writeln!(s, "output {}", join(r.output_stack.iter())).unwrap();
+        writeln!(s, "len {}", self.memory.len).unwrap();
+
+        let mut pages: Vec<_> = self.memory.pages.iter().collect();
+        pages.sort_by_key(|(index, _)| **index);
+        for (index, page) in pages {
+            writeln!(s, "page {} {}", index, join(page.iter())).unwrap();
+        }
+
+        s
+    }
+
+    /// Replaces memory and registers with a state captured by `snapshot`.
+    /// Breakpoints, watchpoints, the tracer and the overflow policy are left
+    /// as they are.
+    pub fn restore(&mut self, snapshot: &str) -> Result<(), IntcodeError> {
+        let mut lines = snapshot.lines();
+
+        if lines.next() != Some(HEADER) {
+            return Err(error("Not an intcode snapshot".to_string()));
+        }
+
+        let mut register = Register {
+            overflow_policy: self.register.overflow_policy,
+            ..Register::default()
+        };
+        let mut memory = Memory {
+            cache: self.memory.cache.as_ref().map(|_| DecodeCache::default()),
+            ..Memory::default()
+        };
+
+        for line in lines {
+            let (key, value) = match line.find(' ') {
+                Some(i) => (&line[..i], &line[i + 1..]),
+                None => (line, ""),
+            };
+
+            match key {
+                "" => {}
+                "instruction_pointer" => register.instruction_pointer = parse(value)?,
+                "relative_base" => register.relative_base = parse(value)?,
+                "flags" => {
+                    let flags = value
+                        .split(' ')
+                        .map(|f| parse::<u8>(f).map(|f| f != 0))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if flags.len() != 6 {
+                        return Err(error(format!("Invalid flags {:?}", value)));
+                    }
+                    register.halt_flag = flags[0];
+                    register.jump_flag = flags[1];
+                    register.wait_flag = flags[2];
+                    register.resume_flag = flags[3];
+                    register.carry_flag = flags[4];
+                    register.sign_flag = flags[5];
+                }
+                "input" => register.input_stack = parse_list(value)?.into_iter().rev().collect(),
+                "output" => register.output_stack = parse_list(value)?.into_iter().collect(),
+                "len" => memory.len = parse(value)?,
+                "page" => {
+                    let (index, values) = match value.find(' ') {
+                        Some(i) => (&value[..i], &value[i + 1..]),
+                        None => return Err(error(format!("Invalid page {:?}", value))),
+                    };
+                    let page = parse_list(values)?;
+                    if page.len() != PAGE_SIZE {
+                        return Err(error(format!("Page {} is not {} cells", index, PAGE_SIZE)));
+                    }
+                    memory.pages.insert(parse(index)?, page.into_boxed_slice());
+                }
+                _ => return Err(error(format!("Unknown field {:?}", key))),
+            }
+        }
+
+        self.register = register;
+        self.memory = memory;
+
+        Ok(())
+    }
+
+    pub fn save_snapshot(&self, path: &str) -> Result<(), IntcodeError> {
+        fs::write(path, self.snapshot()).map_err(|e| error(e.to_string()))
+    }
+
+    pub fn load_snapshot(&mut self, path: &str) -> Result<(), IntcodeError> {
+        let snapshot = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        self.restore(&snapshot)
+    }
+}
+
+#[test]
+fn test_snapshot_restore() {
+    let p = [3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0];
+
+    let mut m = Machine::default();
+    m.init(&p);
+    m.input(2);
+    m.memory.set(5_000, 7);
+
+    assert_eq!(m.resume(), Ok(crate::Status::NeedInput));
+    m.input(3);
+
+    let snapshot = m.snapshot();
+
+    let mut restored = Machine::default();
+    restored.restore(&snapshot).unwrap();
+
+    assert_eq!(restored.register().instruction_pointer(), 2);
+    assert_eq!(restored.register().pending_input(), vec![3]);
+    assert_eq!(restored.memory.get(5_000), 7);
+    assert_eq!(restored.memory.len(), 5_001);
+    assert_eq!(restored.snapshot(), snapshot);
+
+    assert_eq!(restored.run(), Ok(Some(5)));
+    assert_eq!(m.run(), Ok(Some(5)));
+}
+
+#[test]
+fn test_snapshot_file() {
+    let path = std::env::temp_dir().join("intcode_test_snapshot.txt");
+    let path = path.to_str().unwrap();
+
+    let mut m = Machine::default();
+    m.init(&[109, 3, 204, 1, 99]);
+    m.save_snapshot(path).unwrap();
+
+    let mut restored = Machine::default();
+    restored.load_snapshot(path).unwrap();
+    assert_eq!(restored.run(), Ok(Some(99)));
+
+    fs::remove_file(path).unwrap();
+
+    assert_eq!(
+        restored.restore("not a snapshot"),
+        Err(error("Not an intcode snapshot".to_string()))
+    );
+}
+
+#[test]
+fn test_snapshot_big_integers() {
+    use crate::BigInt;
+
+    // Squares its input and outputs the result
+    let mut m = Machine::<BigInt>::new();
+    let program: Vec<BigInt> = [3, 9, 2, 9, 9, 9, 4, 9, 99, 0]
+        .iter()
+        .map(|&v| v.into())
+        .collect();
+    m.init(&program);
+    m.input("12345678901234567890".parse().unwrap());
+
+    let mut restored = Machine::<BigInt>::new();
+    restored.restore(&m.snapshot()).unwrap();
+    assert_eq!(restored.snapshot(), m.snapshot());
+    assert_eq!(
+        restored.run(),
+        Ok(Some(
+            "152415787532388367501905199875019052100".parse().unwrap()
+        ))
+    );
+}
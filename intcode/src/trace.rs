@@ -0,0 +1,72 @@
+use crate::{Access, Cell, Instruction, Memory, Operation};
+use std::fmt;
+
+/// A single executed instruction. Traces are written one record per line,
+/// as space separated fields so that two traces can be compared with `diff`:
+///
+/// ip=4 op=ADD args=9,10,11 read=2,3 write=5 out=-
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record<C = isize> {
+    pub instruction_pointer: usize,
+    pub operation: Operation,
+    pub args: Vec<usize>,
+    pub read: Vec<C>,
+    pub written: Option<C>,
+    pub output: Option<C>,
+}
+
+impl<C: Cell> Record<C> {
+    /// Starts a record for an instruction that is about to be executed
+    pub(crate) fn new(
+        instruction_pointer: usize,
+        instruction: &Instruction,
+        m: &Memory<C>,
+    ) -> Record<C> {
+        Record {
+            instruction_pointer,
+            operation: instruction.operation,
+            args: instruction.args().to_vec(),
+            read: instruction
+                .accesses()
+                .into_iter()
+                .filter(|(_, access)| *access == Access::Read)
+                .map(|(address, _)| m.get(address))
+                .collect(),
+            written: None,
+            output: None,
+        }
+    }
+
+    /// Fills in the results once the instruction has been executed
+    pub(crate) fn complete(&mut self, m: &Memory<C>, output: Option<C>) {
+        self.written = self.operation.write_arg().map(|i| m.get(self.args[i]));
+        self.output = output;
+    }
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    if values.is_empty() {
+        return "-".to_string();
+    }
+    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    values.join(",")
+}
+
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map_or("-".to_string(), |v| v.to_string())
+}
+
+impl<C: Cell> fmt::Display for Record<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ip={} op={} args={} read={} write={} out={}",
+            self.instruction_pointer,
+            self.operation,
+            join(&self.args),
+            join(&self.read),
+            optional(&self.written),
+            optional(&self.output)
+        )
+    }
+}